@@ -1,13 +1,40 @@
 use std::{
+    collections::VecDeque,
     env, fs,
-    io::{self, Cursor},
+    io::{self, BufReader, Cursor, Read},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{Context, Result, ensure};
+use clap::ValueEnum;
 use once_fn::once;
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::{
+    PathExt,
+    conflict::{FileConflict, OnConflict, merge_into, resolve_output},
+    list::{Entry, ListFilter, list_archive},
+    options::{CompressOptions, ExtractFilter, RecompressSection},
+    progress::{self, ProgressMode, ProgressParser, Reporter, format_size, status},
+    staging::{InputList, common_root},
+    verify::{check_integrity, verify_image},
+};
+
+/// 未完成归档的文件名后缀，完整文件名形如 `.foo.dwarfs.XXXXXX.dwarfs-partial`。
+const PARTIAL_SUFFIX: &str = ".dwarfs-partial";
+
+/// 未完成归档超过这么久没有被修改才视为被中断的运行留下的。
+///
+/// mkdwarfs 写入期间会不断更新修改时间，但写完之后还要校验、移动到目标位置，
+/// 这段时间里文件不再变化，另一个进程仍然需要它。
+const STALE_PARTIAL_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 子进程失败时回显的最近输出行数。
+const ERROR_TAIL_LINES: usize = 20;
 
 #[once]
 pub fn temp_dir() -> PathBuf {
@@ -31,6 +58,7 @@ pub fn unpack_all() -> Result<()> {
     let path2 = temp_dir().join("winfsp-x64.dll");
     let path3 = temp_dir().join("mkdwarfs.exe");
     let path4 = temp_dir().join("dwarfsextract.exe");
+    let path5 = temp_dir().join("dwarfsck.exe");
     if !path1.exists() {
         unpack_zstd_to(
             include_bytes!(concat!(env!("OUT_DIR"), "/dwarfs.exe.zst")),
@@ -47,13 +75,16 @@ pub fn unpack_all() -> Result<()> {
         fs::hard_link(&path1, path3)?;
     }
     if !path4.exists() {
-        fs::hard_link(path1, path4)?;
+        fs::hard_link(&path1, path4)?;
+    }
+    if !path5.exists() {
+        fs::hard_link(path1, path5)?;
     }
     Ok(())
 }
 
 /// 运行子进程并检查退出码，非零退出视为错误。
-fn run_checked(command: &mut Command) -> Result<()> {
+pub fn run_checked(command: &mut Command) -> Result<()> {
     let status = command.spawn()?.wait()?;
     ensure!(
         status.success(),
@@ -63,126 +94,598 @@ fn run_checked(command: &mut Command) -> Result<()> {
     Ok(())
 }
 
+/// 捕获子进程的 stdout/stderr，解析为进度并按 [`progress::mode`] 输出；失败时回显最近的输出。
+///
+/// [`ProgressMode::Raw`] 下退化为 [`run_checked`]，直接继承控制台。
+pub fn run_with_progress(command: &mut Command) -> Result<()> {
+    if progress::mode() == ProgressMode::Raw {
+        return run_checked(command);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (tx, rx) = mpsc::channel();
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|r| Box::new(r) as Box<dyn Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|r| Box::new(r) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|reader| {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            progress::for_each_record(BufReader::new(reader), |record| {
+                let _ = tx.send(record.to_string());
+            })
+        })
+    })
+    .collect::<Vec<_>>();
+    drop(tx);
+
+    let mut parser = ProgressParser::default();
+    let mut reporter = Reporter::new();
+    let mut tail = VecDeque::with_capacity(ERROR_TAIL_LINES);
+    for record in rx {
+        if parser.feed(&record) {
+            reporter.report(parser.progress());
+        } else {
+            if tail.len() == ERROR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(record);
+        }
+    }
+    reporter.finish();
+    for reader in readers {
+        // 读取线程只会因管道错误失败，此时以退出码为准
+        let _ = reader.join();
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        for line in &tail {
+            eprintln!("{line}");
+        }
+    }
+    ensure!(
+        status.success(),
+        "`{}` exited with {status}",
+        command.get_program().to_string_lossy()
+    );
+    Ok(())
+}
+
+/// 构造以 `root` 为根目录的 mkdwarfs 命令；给出 `input_list` 时只打包清单中的条目。
+fn mkdwarfs_command(
+    root: &Path,
+    input_list: Option<&Path>,
+    options: &CompressOptions,
+) -> Result<Command> {
+    let mut command = Command::new(temp_dir().join("mkdwarfs.exe"));
+    command
+        .arg("-i")
+        .arg(root)
+        .args(options.to_args())
+        .args(options.filter_args()?);
+    if let Some(input_list) = input_list {
+        command.arg("--input-list").arg(input_list);
+    }
+    Ok(command)
+}
+
+/// 删除 `dir` 中之前被中断的运行留下的未完成归档。
+///
+/// 只删除超过 [`STALE_PARTIAL_AGE`] 未修改的文件，同一文件夹中其他仍在压缩或校验的运行
+/// 写出的未完成归档不受影响。
+fn clean_stale_partials(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let is_stale = entry.metadata().is_ok_and(|metadata| {
+            metadata.is_file()
+                && metadata.modified().is_ok_and(|modified| {
+                    now.duration_since(modified).unwrap_or_default() >= STALE_PARTIAL_AGE
+                })
+        });
+        if name.starts_with('.')
+            && name.ends_with(PARTIAL_SUFFIX)
+            && is_stale
+            && fs::remove_file(entry.path()).is_ok()
+        {
+            status(format_args!(
+                "Removed leftover partial archive: {}",
+                entry.path().display()
+            ));
+        }
+    }
+}
+
+/// 返回路径所在的文件夹，相对路径只有文件名时为当前目录。
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// 写在目标文件旁的临时归档。drop 时自动删除，只有 [`Self::persist`] 后才会出现在目标位置，
+/// 因此 mkdwarfs 或 dwarfsextract 失败或被杀掉时不会留下看似完整的归档文件。
+pub struct PartialOutput {
+    temp: TempPath,
+    target: PathBuf,
+}
+
+impl PartialOutput {
+    pub fn new(target: &Path) -> Result<Self> {
+        let dir = parent_dir(target);
+        clean_stale_partials(dir);
+        let file_name = target
+            .file_name()
+            .with_context(|| format!("file name is empty: {}", target.display()))?;
+        let temp = tempfile::Builder::new()
+            .prefix(&format!(".{}.", file_name.to_string_lossy()))
+            .suffix(PARTIAL_SUFFIX)
+            .tempfile_in(dir)?
+            .into_temp_path();
+        Ok(Self {
+            temp,
+            target: target.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// 把临时归档改名到目标位置。`overwrite` 为 `false` 时目标已存在会报错而不是覆盖。
+    pub fn persist(self, overwrite: bool) -> Result<()> {
+        let result = if overwrite {
+            self.temp.persist(&self.target)
+        } else {
+            self.temp.persist_noclobber(&self.target)
+        };
+        result.map_err(|e| e.error).with_context(|| {
+            format!(
+                "Failed to move archive into place: {}",
+                self.target.display()
+            )
+        })
+    }
+}
+
+/// 压缩成功后删除源文件的方式。
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteMode {
+    /// Move the sources to the Recycle Bin
+    Recycle,
+    /// Delete the sources permanently
+    Permanent,
+}
+
+/// 压缩流程本身（而非 mkdwarfs 参数）的设置。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressBehavior {
+    /// 输出路径已存在时的处理策略
+    pub on_conflict: OnConflict,
+    /// 压缩后是否校验新镜像
+    pub verify: bool,
+    /// 校验通过后删除源文件；设置后总会校验
+    pub delete_source: Option<DeleteMode>,
+}
+
+/// 删除符号链接或 junction 本身，不进入它指向的文件夹。
+///
+/// Windows 上指向文件夹的链接只能用 `remove_dir` 删除，`remove_file` 会失败。
+pub fn remove_link(path: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileTypeExt;
+        if fs::symlink_metadata(path)?.file_type().is_symlink_dir() {
+            return fs::remove_dir(path);
+        }
+    }
+    fs::remove_file(path)
+}
+
+/// 删除已归档的源文件或文件夹。
+fn delete_sources(inputs: &[PathBuf], mode: DeleteMode) -> Result<()> {
+    match mode {
+        DeleteMode::Recycle => {
+            trash::delete_all(inputs).context("Failed to move sources to the Recycle Bin")?;
+        },
+        DeleteMode::Permanent => {
+            for input in inputs {
+                let file_type = fs::symlink_metadata(input)?.file_type();
+                if file_type.is_symlink() {
+                    remove_link(input)?;
+                } else if file_type.is_dir() {
+                    fs::remove_dir_all(input)?;
+                } else {
+                    fs::remove_file(input)?;
+                }
+            }
+        },
+    }
+    for input in inputs {
+        status(format_args!("Removed source: {}", input.display()));
+    }
+    Ok(())
+}
+
+/// 要打包的内容。
+enum Source {
+    /// 整个文件夹，直接作为 mkdwarfs 的 `-i`
+    Folder(PathBuf),
+    /// 公共父目录下选中的条目，通过 `--input-list` 传给 mkdwarfs
+    Listed(InputList),
+}
+
+impl Source {
+    /// 用户给出的输入路径，即 `--delete-source` 要删除的对象。
+    fn inputs(&self) -> Result<Vec<PathBuf>> {
+        match self {
+            Self::Folder(root) => Ok(vec![std::path::absolute(root)?]),
+            Self::Listed(list) => Ok(list.inputs.clone()),
+        }
+    }
+}
+
+/// 运行 mkdwarfs 把 `source` 压缩到 `output_path`，需要时在归档移到目标位置之前校验它。
+/// `output_path` 必须已经由调用方用 [`resolve_output`] 处理过冲突。
+///
+/// 设置了 [`CompressBehavior::delete_source`] 时，归档校验通过并移到目标位置后才删除源文件。
+fn run_mkdwarfs(
+    source: &Source,
+    output_path: &Path,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
+) -> Result<()> {
+    if behavior.delete_source.is_some() {
+        ensure!(
+            !options.has_filters(),
+            "--delete-source can't be combined with filters, excluded files would be lost"
+        );
+        let output_path = std::path::absolute(output_path)?;
+        for input in source.inputs()? {
+            ensure!(
+                !output_path.starts_with(&input),
+                "Refusing to delete {} because the archive is written inside it",
+                input.display()
+            );
+        }
+    }
+    let partial = PartialOutput::new(output_path)?;
+    let list_file;
+    let mut command = match source {
+        Source::Folder(root) => mkdwarfs_command(root, None, options)?,
+        Source::Listed(list) => {
+            list_file = list.write()?;
+            mkdwarfs_command(&list.root, Some(list_file.path()), options)?
+        },
+    };
+    // 临时文件已由我们创建，需要 --force 让 mkdwarfs 覆盖它
+    command.arg("-o").arg(partial.path()).arg("--force");
+    if progress::mode() != ProgressMode::Raw {
+        command.arg("--progress=simple");
+    }
+    run_with_progress(&mut command)?;
+    if behavior.verify || behavior.delete_source.is_some() {
+        let complete = !options.has_filters();
+        match source {
+            Source::Folder(root) => {
+                verify_image(partial.path(), &InputList::from_folder(root)?, complete)?;
+            },
+            Source::Listed(list) => verify_image(partial.path(), list, complete)?,
+        }
+    }
+    partial.persist(behavior.on_conflict == OnConflict::Overwrite)?;
+    if let Some(mode) = behavior.delete_source {
+        delete_sources(&source.inputs()?, mode)?;
+    }
+    Ok(())
+}
+
 /// 压缩文件夹到 .dwarfs 文件。
 pub fn compress_folder_to_dwarfs(
     input_path: impl AsRef<Path>,
     output_path: impl AsRef<Path>,
-    compression_level: Option<i32>,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
 ) -> Result<()> {
     let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
-    unpack_all()?;
+    options.validate()?;
     ensure!(
         input_path.is_dir(),
         "Input path is not a directory: {}",
         input_path.display()
     );
-    ensure!(
-        !output_path.exists(),
-        "Output path already exists: {}",
-        output_path.display()
-    );
-    let mut command = Command::new(temp_dir().join("mkdwarfs.exe"));
-    command.arg("-i").arg(input_path).arg("-o").arg(output_path);
-    if let Some(level) = compression_level {
-        command.arg("-l").arg(level.to_string());
+    let Some(output_path) = resolve_output(output_path.as_ref(), behavior.on_conflict, true)?
+    else {
+        return Ok(());
+    };
+    compress_folder_to_resolved(input_path, &output_path, options, behavior)
+}
+
+/// 与 [`compress_folder_to_dwarfs`] 相同，但 `output_path` 已经由调用方用 [`resolve_output`]
+/// 处理过冲突，不会再次决定输出路径。
+pub fn compress_folder_to_resolved(
+    input_path: &Path,
+    output_path: &Path,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
+) -> Result<()> {
+    unpack_all()?;
+    run_mkdwarfs(
+        &Source::Folder(input_path.to_path_buf()),
+        output_path,
+        options,
+        behavior,
+    )
+}
+
+/// 解压流程的设置。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecompressBehavior {
+    /// 目标已存在时的处理策略
+    pub on_conflict: OnConflict,
+    /// 归档根目录只有一个条目时，把它直接放到输出路径所在的文件夹，而不是再套一层同名文件夹
+    pub smart: bool,
+    /// 目标已存在时解压合并到其中，已有文件按此策略处理
+    pub merge: Option<FileConflict>,
+}
+
+/// 归档根目录下唯一的条目，返回其名字和是否为文件夹。类型按列表中的权限判断，因此空文件夹
+/// 也算文件夹。根目录下有多个条目或为空时返回 `None`。
+fn single_root_entry(entries: &[Entry]) -> Option<(String, bool)> {
+    let mut roots = entries.iter().filter(|entry| !entry.path.contains('/'));
+    match (roots.next(), roots.next()) {
+        (Some(root), None) => Some((root.path.clone(), root.is_dir())),
+        _ => None,
+    }
+}
+
+/// 运行 dwarfsextract 把镜像解压到 `output_path` 文件夹中。
+fn run_dwarfsextract(input_path: &Path, output_path: &Path, filter_args: &[String]) -> Result<()> {
+    fs::create_dir_all(output_path)?;
+    let mut command = Command::new(temp_dir().join("dwarfsextract.exe"));
+    command
+        .arg("-i")
+        .arg(input_path)
+        .arg("-o")
+        .arg(output_path)
+        .args(filter_args);
+    if progress::mode() != ProgressMode::Raw {
+        command.arg("--stdout-progress");
+    }
+    run_with_progress(&mut command)
+}
+
+/// 列出镜像根目录下的条目，返回唯一的根条目（见 [`single_root_entry`]）。
+fn image_single_root_entry(input_path: &Path) -> Result<Option<(String, bool)>> {
+    let roots = list_archive(input_path, &ListFilter {
+        depth: Some(1),
+        ..ListFilter::default()
+    })?;
+    Ok(single_root_entry(&roots))
+}
+
+/// 把镜像解压到 `target`；给出 `entry`（唯一的根条目及其是否为文件夹）时，`target` 是这个条目
+/// 本身的目标路径，名字可以与条目不同。
+///
+/// `target` 已存在且需要合并时，先解压到旁边的临时文件夹，再按 [`FileConflict`] 逐个文件移入并
+/// 输出摘要；否则按 [`OnConflict`] 决定最终路径。
+fn extract_to(
+    input_path: &Path,
+    target: &Path,
+    entry: Option<&(String, bool)>,
+    filter_args: &[String],
+    behavior: DecompressBehavior,
+) -> Result<()> {
+    let merge = behavior
+        .merge
+        .or((behavior.on_conflict == OnConflict::Overwrite).then_some(FileConflict::Overwrite));
+    let staging = || {
+        tempfile::Builder::new()
+            .prefix(".dwarfs-extract-")
+            .tempdir_in(parent_dir(target))
+    };
+    if let Some(policy) = merge
+        && target.exists()
+    {
+        status(format_args!(
+            "Merging {} into {}",
+            input_path.display(),
+            target.display()
+        ));
+        let staging = staging()?;
+        run_dwarfsextract(input_path, staging.path(), filter_args)?;
+        let source = entry.map_or_else(
+            || staging.path().to_path_buf(),
+            |(name, _)| staging.path().join(name),
+        );
+        merge_into(&source, target, policy)?.print();
+        return Ok(());
+    }
+
+    let keep_extension = entry.is_some_and(|(_, is_dir)| !is_dir);
+    let Some(resolved) = resolve_output(target, behavior.on_conflict, keep_extension)? else {
+        return Ok(());
+    };
+    status(format_args!(
+        "Decompressing  {} to {}",
+        input_path.display(),
+        resolved.display()
+    ));
+    match entry {
+        None => run_dwarfsextract(input_path, &resolved, filter_args),
+        Some((name, _)) if resolved.file_name() == Some(name.as_ref()) => {
+            run_dwarfsextract(input_path, parent_dir(&resolved), filter_args)
+        },
+        Some((name, _)) => {
+            // 换了名字时先解压到同一文件夹下的临时目录，再把唯一的条目改名移出，临时目录随后删除
+            let staging = staging()?;
+            run_dwarfsextract(input_path, staging.path(), filter_args)?;
+            fs::rename(staging.path().join(name), &resolved).with_context(|| {
+                format!("Failed to move extracted {name} to {}", resolved.display())
+            })
+        },
     }
-    run_checked(&mut command)
 }
 
 /// 解压 dwarfs 文件到指定文件夹。
+///
+/// 目标文件夹已存在时按 [`DecompressBehavior::on_conflict`] 处理；设置了
+/// [`DecompressBehavior::merge`] 或策略为 [`OnConflict::Overwrite`] 时则解压合并到已有文件夹中，
+/// 逐个文件处理冲突并报告替换和跳过的文件。`filter` 非空时只提取匹配的条目，路径结构保持不变。
+///
+/// `output_path` 为 `None` 时解压到归档旁边去掉 `.dwarfs` 后缀的文件夹。设置
+/// [`DecompressBehavior::smart`] 且归档根目录只有一个文件或文件夹时，它本身就是解压结果：给出
+/// `output_path` 时正好放在这个路径，否则以原名放在归档旁边，冲突检查也针对这个条目。
 pub fn decompress_dwarfs_to_folder(
     input_path: impl AsRef<Path>,
-    output_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    filter: &ExtractFilter,
+    behavior: DecompressBehavior,
 ) -> Result<()> {
     let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
-    println!(
-        "Decompressing  {} to {}",
-        input_path.display(),
-        output_path.display()
-    );
+    let filter_args = filter.to_args()?;
     unpack_all()?;
     ensure!(
         input_path.is_file(),
         "Input path is not a file: {}",
         input_path.display()
     );
-    fs::create_dir_all(output_path)?;
-    let mut command = Command::new(temp_dir().join("dwarfsextract.exe"));
-    command.arg("-i").arg(input_path).arg("-o").arg(output_path);
-    run_checked(&mut command)
+    let entry = if behavior.smart {
+        image_single_root_entry(input_path)?
+    } else {
+        None
+    };
+    let target = match (&entry, output_path) {
+        (_, Some(output_path)) => output_path.to_path_buf(),
+        (Some((name, _)), None) => parent_dir(input_path).join(name),
+        (None, None) => input_path.rm_ext(),
+    };
+    extract_to(input_path, &target, entry.as_ref(), &filter_args, behavior)
 }
 
-/// 将移入临时文件夹的文件恢复原位、并清理临时文件夹的 RAII guard。
-/// 无论压缩成功还是失败，都保证输入文件不丢。
-struct RestoreGuard {
-    moved_to: PathBuf,
-    original: PathBuf,
-    temp_folder: PathBuf,
+/// 用新的压缩参数重写已有的 .dwarfs 文件，块划分和文件内容不变，无需解压到磁盘。
+///
+/// `output_path` 为 `None` 时原地替换输入文件；新镜像先写到临时文件并通过完整性检查，成功后才
+/// 移到目标位置。
+pub fn recompress_dwarfs(
+    input_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    options: &CompressOptions,
+    section: RecompressSection,
+    on_conflict: OnConflict,
+) -> Result<()> {
+    let input_path = input_path.as_ref();
+    options.validate()?;
+    let ignored = options.layout_options();
+    if !ignored.is_empty() {
+        eprintln!(
+            "Ignored when recompressing: {} (they are fixed when the archive is created)",
+            ignored.join(", ")
+        );
+    }
+    unpack_all()?;
+    ensure!(
+        input_path.is_file(),
+        "Input path is not a file: {}",
+        input_path.display()
+    );
+    let (output_path, overwrite) = match output_path {
+        None => (input_path.to_path_buf(), true),
+        Some(output_path) => {
+            let Some(resolved) = resolve_output(output_path, on_conflict, true)? else {
+                return Ok(());
+            };
+            (resolved, on_conflict == OnConflict::Overwrite)
+        },
+    };
+    status(format_args!(
+        "Recompressing  {} to {}",
+        input_path.display(),
+        output_path.display()
+    ));
+    let partial = PartialOutput::new(&output_path)?;
+    let mut command = Command::new(temp_dir().join("mkdwarfs.exe"));
+    command
+        .arg("-i")
+        .arg(input_path)
+        .arg("-o")
+        .arg(partial.path())
+        .arg("--force")
+        .arg(format!("--recompress={}", section.as_arg()))
+        .args(options.recompress_args());
+    if progress::mode() != ProgressMode::Raw {
+        command.arg("--progress=simple");
+    }
+    run_with_progress(&mut command)?;
+    check_integrity(partial.path())?;
+
+    let old_size = fs::metadata(input_path)?.len();
+    let new_size = fs::metadata(partial.path())?.len();
+    partial.persist(overwrite)?;
+    status(format_args!(
+        "Recompressed {}: {} -> {}",
+        output_path.display(),
+        format_size(old_size),
+        format_size(new_size)
+    ));
+    Ok(())
 }
 
-impl Drop for RestoreGuard {
-    fn drop(&mut self) {
-        if let Err(e) = fs::rename(&self.moved_to, &self.original) {
-            eprintln!(
-                "Failed to restore input file to {}: {e}",
-                self.original.display()
-            );
-            return;
-        }
-        // 文件移走后临时文件夹应为空，用 remove_dir 避免误删用户已有目录
-        if let Err(e) = fs::remove_dir(&self.temp_folder) {
-            eprintln!(
-                "Failed to remove temp folder {}: {e}",
-                self.temp_folder.display()
-            );
-        }
-    }
+/// 以输入的公共父目录为根，通过 mkdwarfs 的 `--input-list` 只打包给出的文件和文件夹。
+fn compress_listed_to_dwarfs(
+    inputs: &[PathBuf],
+    output_path: &Path,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
+) -> Result<()> {
+    let list = InputList::from_inputs(inputs)?;
+    let Some(output_path) = resolve_output(output_path, behavior.on_conflict, true)? else {
+        return Ok(());
+    };
+    run_mkdwarfs(&Source::Listed(list), &output_path, options, behavior)
 }
 
 /// 压缩文件或文件夹到 .dwarfs 文件。
-/// 如果输入是文件，会先创建一个与文件名相同的临时文件夹，将文件移动进去再压缩。
-/// 压缩结束后，会将临时文件夹中的文件移动回原来的位置。
+///
+/// 如果输入是文件，以其所在文件夹为根、通过输入清单只打包这一个文件，原文件不会被移动。
+/// 设置 [`CompressBehavior::delete_source`] 时，校验通过后输入会被移到回收站或永久删除。
 pub fn compress_path_to_dwarfs(
     input_path: impl AsRef<Path>,
     output_path: impl AsRef<Path>,
-    compression_level: Option<i32>,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
 ) -> Result<()> {
     let input_path_ref = input_path.as_ref();
     let output_path_ref = output_path.as_ref();
+    options.validate()?;
     unpack_all()?;
 
     if input_path_ref.is_file() {
-        let file_name = input_path_ref
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("temp_file");
-        let parent = input_path_ref
-            .parent()
-            .with_context(|| format!("can't get parent path of {}", input_path_ref.display()))?;
-        let temp_folder_path = parent.join(file_name);
-        ensure!(
-            !temp_folder_path.exists(),
-            "Temporary folder already exists, refusing to overwrite: {}",
-            temp_folder_path.display()
-        );
-        fs::create_dir(&temp_folder_path)?;
-        let dest_path = temp_folder_path.join(
-            input_path_ref
-                .file_name()
-                .with_context(|| format!("file name is empty: {}", input_path_ref.display()))?,
-        );
-        fs::rename(input_path_ref, &dest_path)?;
-        // 无论压缩成功与否，guard 都会把文件移回原位并清理临时文件夹
-        let _guard = RestoreGuard {
-            moved_to: dest_path,
-            original: input_path_ref.to_path_buf(),
-            temp_folder: temp_folder_path.clone(),
-        };
-        compress_folder_to_dwarfs(&temp_folder_path, output_path_ref, compression_level)?;
+        compress_listed_to_dwarfs(
+            &[input_path_ref.to_path_buf()],
+            output_path_ref,
+            options,
+            behavior,
+        )?;
     } else if input_path_ref.is_dir() {
-        compress_folder_to_dwarfs(input_path_ref, output_path_ref, compression_level)?;
+        compress_folder_to_dwarfs(input_path_ref, output_path_ref, options, behavior)?;
     } else if input_path_ref.exists() {
         anyhow::bail!("Unsupported input path type: {}", input_path_ref.display());
     } else {
@@ -191,10 +694,149 @@ pub fn compress_path_to_dwarfs(
     Ok(())
 }
 
+/// 多个输入时的默认输出路径：放在公共父目录下，以该目录名命名；单个输入时为输入路径加 `.dwarfs`。
+pub fn default_archive_path(inputs: &[PathBuf]) -> Result<PathBuf> {
+    if let [input] = inputs {
+        return Ok(input.add_ext());
+    }
+    let inputs = inputs
+        .iter()
+        .map(std::path::absolute)
+        .collect::<io::Result<Vec<_>>>()?;
+    let root = common_root(&inputs)?;
+    let name = root
+        .file_name()
+        .with_context(|| format!("can't get folder name of {}", root.display()))?;
+    Ok(root.join(name).add_ext())
+}
+
+/// 把多个文件或文件夹压缩到同一个 .dwarfs 文件。
+///
+/// 所有输入以其公共父目录为根，通过 mkdwarfs 的 `--input-list` 只打包选中的条目，
+/// 不会移动或复制用户的数据。只有一个输入时等同于 [`compress_path_to_dwarfs`]。
+pub fn compress_paths_to_dwarfs(
+    inputs: &[PathBuf],
+    output_path: impl AsRef<Path>,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
+) -> Result<()> {
+    if let [input] = inputs {
+        return compress_path_to_dwarfs(input, output_path, options, behavior);
+    }
+    options.validate()?;
+    unpack_all()?;
+    compress_listed_to_dwarfs(inputs, output_path.as_ref(), options, behavior)
+}
+
+/// 试运行：列出按当前过滤规则会被打包的条目，不生成归档。
+pub fn preview_compression(inputs: &[PathBuf], options: &CompressOptions) -> Result<()> {
+    options.validate()?;
+    unpack_all()?;
+    let list_file;
+    let mut command = if let [input] = inputs
+        && input.is_dir()
+    {
+        mkdwarfs_command(input, None, options)?
+    } else {
+        let list = InputList::from_inputs(inputs)?;
+        list_file = list.write()?;
+        mkdwarfs_command(&list.root, Some(list_file.path()), options)?
+    };
+    command.arg("--debug-filter=included");
+    run_checked(&mut command)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn partial_output_appears_only_after_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("a.dwarfs");
+        let partial = PartialOutput::new(&target).unwrap();
+        fs::write(partial.path(), "image").unwrap();
+        assert!(!target.exists());
+        partial.persist(false).unwrap();
+        assert!(fs::read_to_string(&target).unwrap() == "image");
+
+        // 未 persist 的临时文件在 drop 时删除，目标不受影响
+        let partial = PartialOutput::new(&target).unwrap();
+        fs::write(partial.path(), "broken").unwrap();
+        drop(partial);
+        assert!(fs::read_dir(dir.path()).unwrap().count() == 1);
+        assert!(fs::read_to_string(&target).unwrap() == "image");
+    }
+
+    #[test]
+    fn single_root_entry_is_detected_from_listing() {
+        let entry = |path: &str, mode: &str| Entry {
+            path: path.to_string(),
+            mode: mode.to_string(),
+            size: 0,
+            mtime: 0,
+            mtime_resolution: 1,
+            target: None,
+        };
+        let folder = [entry("foo", "drwxr-xr-x"), entry("foo/a.txt", "-rw-r--r--")];
+        assert!(single_root_entry(&folder) == Some(("foo".into(), true)));
+        // 空文件夹按权限判断，不会被当成文件
+        assert!(single_root_entry(&[entry("empty", "drwxr-xr-x")]) == Some(("empty".into(), true)));
+        assert!(
+            single_root_entry(&[entry("data.bin", "-rw-r--r--")])
+                == Some(("data.bin".into(), false))
+        );
+        assert!(
+            single_root_entry(&[entry("a.txt", "-rw-r--r--"), entry("b", "drwxr-xr-x")]).is_none()
+        );
+        assert!(single_root_entry(&[]).is_none());
+    }
+
+    #[test]
+    fn stale_partials_are_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join(".a.dwarfs.abc123.dwarfs-partial");
+        let in_use = dir.path().join(".b.dwarfs.def456.dwarfs-partial");
+        let unrelated = dir.path().join("notes.dwarfs-partial");
+        for path in [&stale, &in_use, &unrelated] {
+            fs::write(path, "").unwrap();
+        }
+        let old = SystemTime::now() - STALE_PARTIAL_AGE - Duration::from_secs(60);
+        for path in [&stale, &unrelated] {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        clean_stale_partials(dir.path());
+        assert!(!stale.exists());
+        // 其他运行刚写完、还在校验的未完成归档不能被删除
+        assert!(in_use.exists());
+        assert!(unrelated.exists());
+    }
+
+    #[test]
+    fn permanent_delete_removes_links_but_not_their_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        let link = dir.path().join("link");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("keep.txt"), "keep").unwrap();
+        #[cfg(windows)]
+        let created = std::os::windows::fs::symlink_dir(&target, &link);
+        #[cfg(not(windows))]
+        let created = std::os::unix::fs::symlink(&target, &link);
+        // 创建符号链接需要开发者模式或管理员权限
+        if created.is_err() {
+            return;
+        }
+        delete_sources(std::slice::from_ref(&link), DeleteMode::Permanent).unwrap();
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(target.join("keep.txt").exists());
+    }
+
     #[test]
     fn run_checked_succeeds_on_zero_exit() {
         let mut cmd = Command::new("cmd");
@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, env, fs, io, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+
+use crate::{
+    conflict::OnConflict,
+    options::{CompressOptions, MountOptions},
+};
+
+/// 配置文件名，位于 `%APPDATA%\windows-dwarfs-tools\` 下。
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// 用户配置，对应 `%APPDATA%\windows-dwarfs-tools\config.toml`。
+///
+/// ```toml
+/// default-preset = "team"
+/// on-conflict = "rename"
+/// verify = true
+///
+/// [menu]
+/// compress-and-remove = true
+///
+/// [mount]
+/// cache-size = "1g"
+/// case-insensitive = true
+///
+/// [presets.team]
+/// compression-level = 7
+/// block-size-bits = 26
+/// order = "nilsimsa"
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// 未指定 `--preset` 时使用的预设名，右键菜单的快速压缩也会使用它。
+    pub default_preset: Option<String>,
+    /// 输出路径已存在时的默认处理策略，命令行的 `--on-conflict` 优先。
+    pub on_conflict: OnConflict,
+    /// 是否默认在压缩后校验归档，命令行的 `--verify` 优先。
+    pub verify: bool,
+    /// 可选的右键菜单项。
+    pub menu: MenuConfig,
+    /// 默认的挂载参数，命令行参数优先。
+    pub mount: MountOptions,
+    /// 用户自定义预设，同名时覆盖内置预设。
+    pub presets: BTreeMap<String, CompressOptions>,
+}
+
+/// 可选的右键菜单项，修改后需要再次不带参数运行程序以更新菜单。
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MenuConfig {
+    /// “Compress and remove”：压缩、校验后把源文件移到回收站
+    pub compress_and_remove: bool,
+}
+
+/// 内置预设。
+fn builtin_presets() -> BTreeMap<String, CompressOptions> {
+    [("store", 0), ("fast", 3), ("balanced", 7), ("max", 9)]
+        .into_iter()
+        .map(|(name, level)| {
+            (name.to_string(), CompressOptions {
+                compression_level: Some(level),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// 程序的数据文件夹 `%APPDATA%\windows-dwarfs-tools`。未设置 `APPDATA` 时返回 `None`。
+pub fn data_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join(env!("CARGO_PKG_NAME")))
+}
+
+/// 配置文件路径。未设置 `APPDATA` 时返回 `None`。
+pub fn config_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+impl Config {
+    /// 读取用户配置；配置文件不存在时返回默认配置。
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content)
+                .with_context(|| format!("Invalid config file: {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        for (name, preset) in &config.presets {
+            preset
+                .validate()
+                .with_context(|| format!("Invalid preset `{name}`"))?;
+        }
+        config.mount.validate().context("Invalid mount options")?;
+        Ok(config)
+    }
+
+    /// 所有可用预设，用户预设覆盖同名内置预设。
+    pub fn presets(&self) -> BTreeMap<String, CompressOptions> {
+        let mut presets = builtin_presets();
+        presets.extend(self.presets.clone());
+        presets
+    }
+
+    /// 按名称查找预设；`name` 为 `None` 时使用 `default-preset`，两者都没有时返回空参数。
+    pub fn preset(&self, name: Option<&str>) -> Result<CompressOptions> {
+        let Some(name) = name.or(self.default_preset.as_deref()) else {
+            return Ok(CompressOptions::default());
+        };
+        let mut presets = self.presets();
+        presets.remove(name).ok_or_else(|| {
+            anyhow!(
+                "Unknown preset `{name}`, available presets: {}",
+                presets.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::InodeOrder;
+
+    #[test]
+    fn empty_config_has_builtin_presets_and_no_default() {
+        let config = Config::parse("").unwrap();
+        assert!(config.preset(None).unwrap() == CompressOptions::default());
+        assert!(config.preset(Some("max")).unwrap().compression_level == Some(9));
+        assert!(config.preset(Some("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn user_presets_override_builtin_and_set_default() {
+        let config = Config::parse(
+            r#"
+            default-preset = "team"
+            on-conflict = "skip"
+
+            [menu]
+            compress-and-remove = true
+
+            [mount]
+            workers = 4
+            log-level = "debug"
+
+            [presets.team]
+            block-size-bits = 26
+            order = "nilsimsa"
+
+            [presets.fast]
+            compression-level = 1
+            "#,
+        )
+        .unwrap();
+        let team = config.preset(None).unwrap();
+        assert!(team.block_size_bits == Some(26));
+        assert!(team.order == Some(InodeOrder::Nilsimsa));
+        assert!(config.preset(Some("fast")).unwrap().compression_level == Some(1));
+        assert!(config.on_conflict == OnConflict::Skip);
+        assert!(config.menu.compress_and_remove);
+        assert!(config.mount.workers == Some(4) && config.mount.log_level.is_some());
+    }
+
+    #[test]
+    fn invalid_presets_are_rejected() {
+        assert!(Config::parse("[presets.bad]\ncompression-level = 12\n").is_err());
+        assert!(Config::parse("[presets.bad]\nunknown-key = 1\n").is_err());
+        assert!(Config::parse("[mount]\ncache-size = \"big\"\n").is_err());
+    }
+}
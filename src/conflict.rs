@@ -0,0 +1,338 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{compress::remove_link, progress::status};
+
+/// 输出路径已存在时的处理策略，压缩和解压共用。
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Abort with an error
+    Fail,
+    /// Replace the existing archive, or extract over the existing folder
+    Overwrite,
+    /// Pick a free name such as `name (2).dwarfs`
+    #[default]
+    Rename,
+    /// Do nothing and report success
+    Skip,
+}
+
+/// 解压合并到已有文件夹时，单个文件已存在的处理策略。
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileConflict {
+    /// Replace the existing file
+    Overwrite,
+    /// Keep the existing file
+    Skip,
+    /// Replace the existing file only if the archived one is newer
+    KeepNewer,
+    /// Keep both, extracting the archived file as `name (2).ext`
+    Rename,
+}
+
+/// 合并摘要中每一类最多列出的路径数。
+const MAX_LISTED_PATHS: usize = 20;
+
+/// 合并的结果，路径均为目标位置。
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    /// 目标中原本不存在、直接移入的条目数（新文件夹整体算一个）
+    pub added: usize,
+    pub replaced: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    /// 原路径和实际写入的新路径
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl MergeSummary {
+    pub fn print(&self) {
+        status(format_args!(
+            "{} added, {} replaced, {} skipped, {} renamed",
+            self.added,
+            self.replaced.len(),
+            self.skipped.len(),
+            self.renamed.len()
+        ));
+        let print_list = |title: &str, paths: &[String]| {
+            if paths.is_empty() {
+                return;
+            }
+            status(format_args!("{title}:"));
+            for path in paths.iter().take(MAX_LISTED_PATHS) {
+                status(format_args!("  {path}"));
+            }
+            if paths.len() > MAX_LISTED_PATHS {
+                status(format_args!(
+                    "  ... and {} more",
+                    paths.len() - MAX_LISTED_PATHS
+                ));
+            }
+        };
+        let display = |paths: &[PathBuf]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect()
+        };
+        print_list("Replaced", &display(&self.replaced));
+        print_list("Skipped", &display(&self.skipped));
+        print_list(
+            "Renamed",
+            &self
+                .renamed
+                .iter()
+                .map(|(from, to)| format!("{} -> {}", from.display(), to.display()))
+                .collect::<Vec<_>>(),
+        );
+    }
+}
+
+/// 把 `source`（文件或文件夹）移动合并到 `dest`，两边都是文件夹时逐项递归，其余冲突按 `policy`
+/// 处理。`source` 应与 `dest` 位于同一卷上，未移走的内容（被跳过的文件）留在原处由调用方清理。
+pub fn merge_into(source: &Path, dest: &Path, policy: FileConflict) -> Result<MergeSummary> {
+    let mut summary = MergeSummary::default();
+    merge_entry(source, dest, policy, &mut summary)?;
+    Ok(summary)
+}
+
+fn merge_entry(
+    source: &Path,
+    dest: &Path,
+    policy: FileConflict,
+    summary: &mut MergeSummary,
+) -> Result<()> {
+    let move_to = |target: &Path| {
+        fs::rename(source, target)
+            .with_context(|| format!("Failed to move extracted file to {}", target.display()))
+    };
+    let source_meta = fs::symlink_metadata(source)?;
+    let dest_meta = match fs::symlink_metadata(dest) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            move_to(dest)?;
+            summary.added += 1;
+            return Ok(());
+        },
+        Err(e) => return Err(e.into()),
+    };
+    if source_meta.is_dir() && dest_meta.is_dir() {
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            merge_entry(
+                &entry.path(),
+                &dest.join(entry.file_name()),
+                policy,
+                summary,
+            )?;
+        }
+        return Ok(());
+    }
+    let replace = match policy {
+        FileConflict::Overwrite => true,
+        FileConflict::Skip => false,
+        FileConflict::KeepNewer => source_meta.modified()? > dest_meta.modified()?,
+        FileConflict::Rename => {
+            let renamed = (2..=u32::MAX)
+                .map(|n| numbered_path(dest, n, !source_meta.is_dir()))
+                .find(|candidate| !candidate.exists())
+                .with_context(|| format!("No free name found for {}", dest.display()))?;
+            move_to(&renamed)?;
+            summary.renamed.push((dest.to_path_buf(), renamed));
+            return Ok(());
+        },
+    };
+    if replace {
+        // 指向文件夹的符号链接和 junction 不算文件夹，只删除链接本身
+        if dest_meta.is_dir() {
+            fs::remove_dir_all(dest)?;
+        } else {
+            remove_link(dest)?;
+        }
+        move_to(dest)?;
+        summary.replaced.push(dest.to_path_buf());
+    } else {
+        summary.skipped.push(dest.to_path_buf());
+    }
+    Ok(())
+}
+
+/// 生成第 `n` 个候选名，例如 `foo (2).dwarfs`。
+///
+/// `keep_extension` 为 `false` 时（用于文件夹）把整个文件名视为主干，`my.folder` 会变成
+/// `my.folder (2)`。
+fn numbered_path(path: &Path, n: u32, keep_extension: bool) -> PathBuf {
+    let (stem, extension) = match (keep_extension, path.file_stem(), path.extension()) {
+        (true, Some(stem), Some(extension)) => (stem, Some(extension)),
+        _ => (path.file_name().unwrap_or_default(), None),
+    };
+    let mut name = OsString::from(stem);
+    name.push(format!(" ({n})"));
+    if let Some(extension) = extension {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// 按策略决定最终的输出路径。返回 `None` 表示应跳过本次操作。
+///
+/// `is_archive` 为 `true` 表示输出是 .dwarfs 文件，`false` 表示输出是解压目标文件夹。
+/// [`OnConflict::Overwrite`] 时原样返回路径，由调用方负责替换。
+pub fn resolve_output(
+    path: &Path,
+    policy: OnConflict,
+    is_archive: bool,
+) -> Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(Some(path.to_path_buf()));
+    }
+    match policy {
+        OnConflict::Fail => bail!(
+            "Output path already exists: {} (use --on-conflict to overwrite, rename or skip)",
+            path.display()
+        ),
+        OnConflict::Overwrite => {
+            if is_archive && path.is_dir() {
+                bail!(
+                    "Refusing to overwrite a folder with an archive: {}",
+                    path.display()
+                );
+            }
+            Ok(Some(path.to_path_buf()))
+        },
+        OnConflict::Rename => {
+            let renamed = (2..=u32::MAX)
+                .map(|n| numbered_path(path, n, is_archive))
+                .find(|candidate| !candidate.exists())
+                .with_context(|| format!("No free name found for {}", path.display()))?;
+            status(format_args!(
+                "Output path already exists, using {} instead",
+                renamed.display()
+            ));
+            Ok(Some(renamed))
+        },
+        OnConflict::Skip => {
+            status(format_args!(
+                "Output path already exists, skipped: {}",
+                path.display()
+            ));
+            Ok(None)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn numbered_names_keep_archive_extension_only() {
+        assert!(
+            numbered_path(Path::new("dir/foo.dwarfs"), 2, true) == Path::new("dir/foo (2).dwarfs")
+        );
+        assert!(
+            numbered_path(Path::new("foo.tar.dwarfs"), 3, true) == Path::new("foo.tar (3).dwarfs")
+        );
+        assert!(numbered_path(Path::new("my.folder"), 2, false) == Path::new("my.folder (2)"));
+    }
+
+    #[test]
+    fn rename_picks_first_free_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("foo.dwarfs");
+        fs::write(&target, "").unwrap();
+        fs::write(dir.path().join("foo (2).dwarfs"), "").unwrap();
+        let resolved = resolve_output(&target, OnConflict::Rename, true).unwrap();
+        assert!(resolved == Some(dir.path().join("foo (3).dwarfs")));
+    }
+
+    #[test]
+    fn other_policies_on_existing_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("foo.dwarfs");
+        assert!(resolve_output(&target, OnConflict::Fail, true).unwrap() == Some(target.clone()));
+        fs::write(&target, "").unwrap();
+        assert!(resolve_output(&target, OnConflict::Fail, true).is_err());
+        assert!(
+            resolve_output(&target, OnConflict::Skip, true)
+                .unwrap()
+                .is_none()
+        );
+        assert!(resolve_output(&target, OnConflict::Overwrite, true).unwrap() == Some(target));
+        assert!(resolve_output(dir.path(), OnConflict::Overwrite, true).is_err());
+    }
+
+    /// 在 `root` 下建立 `source/` 和 `dest/`，两边都有 `same.txt` 和 `sub/both.txt`。
+    fn merge_fixture(root: &Path) -> (PathBuf, PathBuf) {
+        let source = root.join("source");
+        let dest = root.join("dest");
+        for dir in [&source, &dest] {
+            fs::create_dir_all(dir.join("sub")).unwrap();
+        }
+        fs::write(source.join("same.txt"), "archived").unwrap();
+        fs::write(source.join("sub/both.txt"), "archived").unwrap();
+        fs::write(source.join("sub/new.txt"), "archived").unwrap();
+        fs::write(dest.join("same.txt"), "existing").unwrap();
+        fs::write(dest.join("sub/both.txt"), "existing").unwrap();
+        (source, dest)
+    }
+
+    #[test]
+    fn merge_reports_replaced_and_skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, dest) = merge_fixture(dir.path());
+        let summary = merge_into(&source, &dest, FileConflict::Skip).unwrap();
+        assert!(summary.added == 1 && summary.skipped.len() == 2);
+        assert!(fs::read_to_string(dest.join("same.txt")).unwrap() == "existing");
+        assert!(fs::read_to_string(dest.join("sub/new.txt")).unwrap() == "archived");
+
+        let dir = tempfile::tempdir().unwrap();
+        let (source, dest) = merge_fixture(dir.path());
+        let summary = merge_into(&source, &dest, FileConflict::Overwrite).unwrap();
+        assert!(summary.replaced.len() == 2 && summary.skipped.is_empty());
+        assert!(fs::read_to_string(dest.join("sub/both.txt")).unwrap() == "archived");
+    }
+
+    #[test]
+    fn merge_renames_conflicting_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, dest) = merge_fixture(dir.path());
+        let summary = merge_into(&source, &dest, FileConflict::Rename).unwrap();
+        assert!(summary.renamed.len() == 2);
+        assert!(fs::read_to_string(dest.join("same.txt")).unwrap() == "existing");
+        assert!(fs::read_to_string(dest.join("same (2).txt")).unwrap() == "archived");
+        assert!(fs::read_to_string(dest.join("sub/both (2).txt")).unwrap() == "archived");
+    }
+
+    #[test]
+    fn merge_overwrites_links_to_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, dest) = merge_fixture(dir.path());
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("keep.txt"), "keep").unwrap();
+        fs::write(source.join("link"), "archived").unwrap();
+        #[cfg(windows)]
+        let created = std::os::windows::fs::symlink_dir(&target, dest.join("link"));
+        #[cfg(not(windows))]
+        let created = std::os::unix::fs::symlink(&target, dest.join("link"));
+        // 创建符号链接需要开发者模式或管理员权限
+        if created.is_err() {
+            return;
+        }
+        let summary = merge_into(&source, &dest, FileConflict::Overwrite).unwrap();
+        assert!(summary.replaced.contains(&dest.join("link")));
+        assert!(fs::read_to_string(dest.join("link")).unwrap() == "archived");
+        assert!(target.join("keep.txt").exists());
+    }
+}
@@ -0,0 +1,391 @@
+use std::{collections::BTreeMap, fs, path::Path, time::UNIX_EPOCH};
+
+use anyhow::{Result, ensure};
+use serde::Serialize;
+
+use crate::{
+    compress::unpack_all,
+    list::{Entry, ListFilter, list_archive},
+    staging::InputList,
+    verify::{archive_checksums, archive_path, sha256_file},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Directory,
+    File,
+    Symlink,
+    Other,
+}
+
+/// 参与比较的条目属性。
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Item {
+    kind: Kind,
+    size: u64,
+    /// Unix 时间戳（秒）
+    mtime: Option<u64>,
+    /// `mtime` 的精度（秒）
+    mtime_resolution: u64,
+    target: Option<String>,
+    /// SHA-256，仅在使用 `--hash` 时计算
+    hash: Option<String>,
+}
+
+impl From<Entry> for Item {
+    fn from(entry: Entry) -> Self {
+        let kind = match entry.mode.chars().next() {
+            Some('d') => Kind::Directory,
+            Some('-') => Kind::File,
+            Some('l') => Kind::Symlink,
+            _ => Kind::Other,
+        };
+        Self {
+            kind,
+            size: entry.size,
+            mtime: Some(entry.mtime),
+            mtime_resolution: entry.mtime_resolution,
+            target: entry.target,
+            hash: None,
+        }
+    }
+}
+
+/// 以归档内的 `/` 分隔路径为键。
+type Snapshot = BTreeMap<String, Item>;
+
+fn entries_snapshot(entries: Vec<Entry>) -> Snapshot {
+    entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), Item::from(entry)))
+        .collect()
+}
+
+fn archive_snapshot(image: &Path, hash: bool) -> Result<Snapshot> {
+    let mut snapshot = entries_snapshot(list_archive(image, &ListFilter::default())?);
+    if hash {
+        unpack_all()?;
+        for (path, digest) in archive_checksums(image)? {
+            if let Some(item) = snapshot.get_mut(&path) {
+                item.hash = Some(digest);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+fn folder_snapshot(folder: &Path, hash: bool) -> Result<Snapshot> {
+    let list = InputList::from_folder(folder)?;
+    let mut snapshot = Snapshot::new();
+    for entry in &list.entries {
+        let path = list.root.join(entry);
+        let metadata = fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_dir() {
+            Kind::Directory
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            Kind::Other
+        };
+        let target = (kind == Kind::Symlink)
+            .then(|| fs::read_link(&path))
+            .transpose()?
+            .map(|target| target.to_string_lossy().replace('\\', "/"));
+        snapshot.insert(archive_path(entry), Item {
+            kind,
+            size: if kind == Kind::File {
+                metadata.len()
+            } else {
+                0
+            },
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|elapsed| elapsed.as_secs()),
+            mtime_resolution: 1,
+            target,
+            hash: (hash && kind == Kind::File)
+                .then(|| sha256_file(&path))
+                .transpose()?,
+        });
+    }
+    Ok(snapshot)
+}
+
+/// 读取归档或文件夹的内容。
+fn snapshot(path: &Path, hash: bool) -> Result<Snapshot> {
+    ensure!(path.exists(), "Path does not exist: {}", path.display());
+    if path.is_dir() {
+        folder_snapshot(path, hash)
+    } else {
+        archive_snapshot(path, hash)
+    }
+}
+
+/// 条目的哪一项发生了变化。
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Change {
+    Kind,
+    Size,
+    Mtime,
+    Content,
+    Target,
+}
+
+impl Change {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Kind => "type",
+            Self::Size => "size",
+            Self::Mtime => "mtime",
+            Self::Content => "content",
+            Self::Target => "target",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Modified {
+    pub path: String,
+    pub changes: Vec<Change>,
+}
+
+/// 比较结果，`--json` 时原样序列化输出。
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<Modified>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// 每行一个条目：`+` 新增，`-` 删除，`M` 修改并注明变化的属性。
+    pub fn print(&self) {
+        for path in &self.added {
+            println!("+ {path}");
+        }
+        for path in &self.removed {
+            println!("- {path}");
+        }
+        for modified in &self.modified {
+            let changes = modified
+                .changes
+                .iter()
+                .map(|change| change.name())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("M {} ({changes})", modified.path);
+        }
+        if self.is_empty() {
+            println!("No differences");
+        } else {
+            println!(
+                "{} added, {} removed, {} modified",
+                self.added.len(),
+                self.removed.len(),
+                self.modified.len()
+            );
+        }
+    }
+}
+
+/// 修改时间按两侧中较低的精度比较。
+fn same_mtime(old: &Item, new: &Item) -> bool {
+    let resolution = old.mtime_resolution.max(new.mtime_resolution).max(1);
+    old.mtime.map(|secs| secs / resolution) == new.mtime.map(|secs| secs / resolution)
+}
+
+/// 两个条目之间的差异。文件夹只比较类型；哈希只在两侧都有时比较。
+fn changes(old: &Item, new: &Item, ignore_mtime: bool) -> Vec<Change> {
+    if old.kind != new.kind {
+        return vec![Change::Kind];
+    }
+    let mut changes = Vec::new();
+    match old.kind {
+        Kind::File => {
+            if old.size != new.size {
+                changes.push(Change::Size);
+            }
+            if !ignore_mtime && !same_mtime(old, new) {
+                changes.push(Change::Mtime);
+            }
+            if let (Some(old_hash), Some(new_hash)) = (&old.hash, &new.hash)
+                && old_hash != new_hash
+            {
+                changes.push(Change::Content);
+            }
+        },
+        Kind::Symlink if old.target != new.target => changes.push(Change::Target),
+        _ => {},
+    }
+    changes
+}
+
+fn compare(old: &Snapshot, new: &Snapshot, ignore_mtime: bool) -> DiffReport {
+    let mut report = DiffReport::default();
+    for (path, old_item) in old {
+        match new.get(path) {
+            Some(new_item) => {
+                let changes = changes(old_item, new_item, ignore_mtime);
+                if !changes.is_empty() {
+                    report.modified.push(Modified {
+                        path: path.clone(),
+                        changes,
+                    });
+                }
+            },
+            None => report.removed.push(path.clone()),
+        }
+    }
+    report.added = new
+        .keys()
+        .filter(|path| !old.contains_key(*path))
+        .cloned()
+        .collect();
+    report
+}
+
+/// 比较两个 dwarfs 归档，或归档与文件夹（两侧都可以是文件夹）。
+pub fn diff(old: &Path, new: &Path, hash: bool, ignore_mtime: bool) -> Result<DiffReport> {
+    Ok(compare(
+        &snapshot(old, hash)?,
+        &snapshot(new, hash)?,
+        ignore_mtime,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, mtime: u64, hash: Option<&str>) -> Item {
+        Item {
+            kind: Kind::File,
+            size,
+            mtime: Some(mtime),
+            mtime_resolution: 1,
+            target: None,
+            hash: hash.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn compare_reports_added_removed_and_modified() {
+        let dir = Item {
+            kind: Kind::Directory,
+            size: 0,
+            mtime: None,
+            mtime_resolution: 1,
+            target: None,
+            hash: None,
+        };
+        let old = Snapshot::from([
+            ("docs".to_string(), dir.clone()),
+            ("docs/a".to_string(), file(1, 1, Some("x"))),
+            ("gone".to_string(), file(1, 1, None)),
+            ("same".to_string(), file(1, 1, None)),
+        ]);
+        let new = Snapshot::from([
+            ("docs".to_string(), file(0, 1, None)),
+            ("docs/a".to_string(), file(1, 2, Some("y"))),
+            ("new".to_string(), dir),
+            ("same".to_string(), file(1, 1, Some("z"))),
+        ]);
+        let report = compare(&old, &new, false);
+        assert!(report.added == ["new"] && report.removed == ["gone"]);
+        assert!(
+            report.modified
+                == [
+                    Modified {
+                        path: "docs".to_string(),
+                        changes: vec![Change::Kind],
+                    },
+                    Modified {
+                        path: "docs/a".to_string(),
+                        changes: vec![Change::Mtime, Change::Content],
+                    },
+                ]
+        );
+        assert!(compare(&old, &old, false).is_empty());
+        assert!(compare(&old, &new, true).modified[1].changes == [Change::Content]);
+    }
+
+    #[test]
+    fn folder_snapshot_records_kinds_sizes_and_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/abc.txt"), "abc").unwrap();
+        let snapshot = folder_snapshot(dir.path(), true).unwrap();
+        assert!(snapshot.keys().collect::<Vec<_>>() == ["sub", "sub/abc.txt"]);
+        assert!(snapshot["sub"].kind == Kind::Directory && snapshot["sub"].hash.is_none());
+        let file = &snapshot["sub/abc.txt"];
+        assert!(file.kind == Kind::File && file.size == 3 && file.mtime.is_some());
+        assert!(
+            file.hash
+                .as_deref()
+                .is_some_and(|hash| hash.starts_with("ba7816bf"))
+        );
+    }
+
+    /// dwarfsck 以本地时间输出，精确到分钟。
+    fn dwarfsck_time(secs: u64) -> String {
+        use windows::Win32::{
+            Foundation::SYSTEMTIME, System::Time::SystemTimeToTzSpecificLocalTime,
+        };
+
+        let utc = crate::list::format_timestamp(secs);
+        let field = |range: std::ops::Range<usize>| utc[range].parse().unwrap();
+        let utc = SYSTEMTIME {
+            wYear: field(0..4),
+            wMonth: field(5..7),
+            wDay: field(8..10),
+            wHour: field(11..13),
+            wMinute: field(14..16),
+            ..SYSTEMTIME::default()
+        };
+        let mut local = SYSTEMTIME::default();
+        unsafe { SystemTimeToTzSpecificLocalTime(None, &raw const utc, &raw mut local) }.unwrap();
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            local.wYear, local.wMonth, local.wDay, local.wHour, local.wMinute
+        )
+    }
+
+    #[test]
+    fn archive_listings_and_folders_agree_on_mtime() {
+        // 与 tests/fixtures/default.dwarfs 中的 hello.txt 相同
+        let mtime = 1_700_000_000;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        fs::write(&path, "Hello, dwarfs!\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+            .unwrap();
+        let folder = folder_snapshot(dir.path(), false).unwrap();
+        let image = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/default.dwarfs");
+        let native = archive_snapshot(&image, false).unwrap();
+        let listing = format!("-rw-r--r-- 0/0 15 {} hello.txt\n", dwarfsck_time(mtime));
+        let dwarfsck = entries_snapshot(crate::list::parse_listing(&listing).unwrap());
+        for archive in [&native, &dwarfsck] {
+            assert!(changes(&archive["hello.txt"], &folder["hello.txt"], false).is_empty());
+        }
+        // 分钟精度之外的差异仍会被发现
+        let later = Item {
+            mtime: Some(mtime + 60),
+            ..folder["hello.txt"].clone()
+        };
+        assert!(changes(&dwarfsck["hello.txt"], &later, false) == [Change::Mtime]);
+    }
+}
@@ -3,10 +3,21 @@ use std::env;
 use anyhow::{Result, anyhow};
 use winreg::{RegKey, enums::*};
 
+use crate::config::MenuConfig;
+
 const MENU_NAME: &str = env!("CARGO_PKG_NAME"); // Main menu item name
 const FILE_SHELL_PATH: &str = "*\\shell"; // Applies to all files
 const DIRECTORY_SHELL_PATH: &str = "Directory\\shell"; // Applies to folders themselves and folder background
 const FOLDER_SHELL_PATH: &str = "Folder\\shell"; // Primarily applies to folder items themselves
+const FILE_ASSOCIATIONS_PATH: &str = "SystemFileAssociations"; // Per-extension verbs
+const DWARFS_SHELL_PATH: &str = "SystemFileAssociations\\.dwarfs\\shell"; // Applies to dwarfs images only
+
+// Extensions that get the top-level "Import to dwarfs" verb
+const IMPORT_EXTENSIONS: [&str; 6] = [".zip", ".tar", ".tgz", ".tbz2", ".txz", ".tzst"];
+
+// Compression extensions of `.tar.*` files. Verbs are registered by the last extension only, so
+// the import verb registered for these is restricted to `*.tar.<ext>` names via `AppliesTo`
+const COMPRESSED_TAR_EXTENSIONS: [&str; 4] = [".gz", ".bz2", ".xz", ".zst"];
 
 // Subcommand definition
 struct SubCommandInfo<'a> {
@@ -30,7 +41,7 @@ const SUB_COMMANDS: [SubCommandInfo; 5] = [
     SubCommandInfo {
         key_name: "DecompressQuick",
         display_name: "Quick Decompress",
-        arg_template: "\"{}\" d \"%1\"",
+        arg_template: "\"{}\" d --smart \"%1\"",
     },
     SubCommandInfo {
         key_name: "DecompressTo",
@@ -40,15 +51,61 @@ const SUB_COMMANDS: [SubCommandInfo; 5] = [
     SubCommandInfo {
         key_name: "Mount",
         display_name: "Mount",
-        arg_template: "\"{}\" m \"%1\"",
+        arg_template: "\"{}\" m --detach \"%1\"",
     },
 ];
 
+// Top-level verbs that only work on `.dwarfs` images. Key names start with MENU_NAME so they
+// don't clash with verbs of other programs
+const DWARFS_SUB_COMMANDS: [SubCommandInfo; 4] = [
+    SubCommandInfo {
+        key_name: concat!(env!("CARGO_PKG_NAME"), ".Recompress"),
+        display_name: "Recompress",
+        arg_template: "\"{}\" recompress \"%1\"",
+    },
+    SubCommandInfo {
+        key_name: concat!(env!("CARGO_PKG_NAME"), ".MountHere"),
+        display_name: "Mount here",
+        arg_template: "\"{}\" m --detach --here \"%1\"",
+    },
+    SubCommandInfo {
+        key_name: concat!(env!("CARGO_PKG_NAME"), ".MountStatus"),
+        display_name: "Mount status",
+        arg_template: "\"{}\" mounts \"%1\"",
+    },
+    SubCommandInfo {
+        // Not just "Properties", which Explorer already shows for every file
+        key_name: concat!(env!("CARGO_PKG_NAME"), ".Properties"),
+        display_name: "dwarfs properties",
+        arg_template: "\"{}\" info \"%1\"",
+    },
+];
+
+// Optional subcommands, only added when enabled in the `[menu]` section of the config file
+const COMPRESS_AND_REMOVE: SubCommandInfo = SubCommandInfo {
+    key_name: "CompressAndRemove",
+    display_name: "Compress and remove",
+    arg_template: "\"{}\" c --delete-source \"%1\"",
+};
+
+// Top-level verb for archives that can be imported, keyed by MENU_NAME so it's removed with the
+// rest
+const IMPORT: SubCommandInfo = SubCommandInfo {
+    key_name: MENU_NAME,
+    display_name: "Import to dwarfs",
+    arg_template: "\"{}\" import \"%1\"",
+};
+
+/// Returns each optional subcommand together with whether it is enabled.
+const fn optional_sub_commands(menu: &MenuConfig) -> [(&'static SubCommandInfo<'static>, bool); 1] {
+    [(&COMPRESS_AND_REMOVE, menu.compress_and_remove)]
+}
+
 /// Adds context menu entries.
 ///
 /// Adds an expandable context menu for files and folders, with subcommands defined directly under
-/// the main menu's shell subkey.
-pub fn add_context_menu_entries() -> Result<()> {
+/// the main menu's shell subkey. Optional subcommands are added or removed according to `menu`.
+pub fn add_context_menu_entries(menu: &MenuConfig) -> Result<()> {
     let current_exe = env::current_exe()?;
     let exe_path = current_exe
         .to_str()
@@ -60,11 +117,34 @@ pub fn add_context_menu_entries() -> Result<()> {
         .or_else(|_| hkcu.create_subkey("Software\\Classes").map(|x| x.0))?;
 
     // Add menus for different association types
-    add_menu_for_shell_path_prefix(&classes_key, FILE_SHELL_PATH, exe_path)?;
+    add_menu_for_shell_path_prefix(&classes_key, FILE_SHELL_PATH, exe_path, menu)?;
     // "Directory" for folders and folder background
-    add_menu_for_shell_path_prefix(&classes_key, DIRECTORY_SHELL_PATH, exe_path)?;
+    add_menu_for_shell_path_prefix(&classes_key, DIRECTORY_SHELL_PATH, exe_path, menu)?;
     // "Folder" is also usually recommended to ensure coverage for folder items
-    add_menu_for_shell_path_prefix(&classes_key, FOLDER_SHELL_PATH, exe_path)?;
+    add_menu_for_shell_path_prefix(&classes_key, FOLDER_SHELL_PATH, exe_path, menu)?;
+    // Verbs for dwarfs images directly in their context menu
+    let (dwarfs_shell_key, _) = classes_key.create_subkey(DWARFS_SHELL_PATH)?;
+    for sc_info in &DWARFS_SUB_COMMANDS {
+        add_sub_command(&dwarfs_shell_key, sc_info, exe_path)?;
+    }
+    // "Import to dwarfs" directly in the context menu of zip/tar archives
+    for extension in IMPORT_EXTENSIONS {
+        let (shell_key, _) =
+            classes_key.create_subkey(format!("{FILE_ASSOCIATIONS_PATH}\\{extension}\\shell"))?;
+        add_sub_command(&shell_key, &IMPORT, exe_path)?;
+    }
+    for extension in COMPRESSED_TAR_EXTENSIONS {
+        let (shell_key, _) =
+            classes_key.create_subkey(format!("{FILE_ASSOCIATIONS_PATH}\\{extension}\\shell"))?;
+        add_sub_command(&shell_key, &IMPORT, exe_path)?;
+        // Hide the verb for plain compressed files such as `notes.txt.gz`
+        shell_key
+            .open_subkey_with_flags(IMPORT.key_name, KEY_WRITE)?
+            .set_value(
+                "AppliesTo",
+                &format!("System.FileName:\"*.tar{extension}\""),
+            )?;
+    }
 
     println!("Successfully added context menu entries: {MENU_NAME}");
     Ok(())
@@ -76,6 +156,7 @@ fn add_menu_for_shell_path_prefix(
     classes_key: &RegKey,    // HKCU\Software\Classes
     shell_path_prefix: &str, // E.g., "*\\shell", "Directory\\shell"
     exe_path: &str,
+    menu: &MenuConfig,
 ) -> Result<()> {
     // 1. Create the main menu item key, e.g., HKCU\Software\Classes\*\shell\Zstd Tool
     let (main_menu_key, _) =
@@ -98,24 +179,47 @@ fn add_menu_for_shell_path_prefix(
 
     // 3. Add each subcommand item under this "shell" subkey
     for sc_info in &SUB_COMMANDS {
-        // Create the subcommand item key, e.g., HKCU\Software\Classes\*\shell\Zstd
-        // Tool\shell\CompressQuick
-        let (sub_command_entry_key, _) = sub_menu_container_key.create_subkey(sc_info.key_name)?;
-
-        // Set the display name for the subcommand item
-        sub_command_entry_key.set_value("MUIVerb", &sc_info.display_name)?;
-        // (Optional) Set an icon for the subcommand item
-        // sub_command_entry_key.set_value("Icon", &format!("\"{}\",0", exe_path))?;
-
-        // Create the command subkey to store the actual command to execute
-        let (command_key, _) = sub_command_entry_key.create_subkey("command")?;
-        let command_str = sc_info.arg_template.replace("{}", exe_path);
-        command_key.set_value("", &command_str)?; // Command string as the default value of the command key
+        add_sub_command(&sub_menu_container_key, sc_info, exe_path)?;
+    }
+
+    // 4. Add enabled optional subcommands, and remove disabled ones left by a previous install
+    for (sc_info, enabled) in optional_sub_commands(menu) {
+        if enabled {
+            add_sub_command(&sub_menu_container_key, sc_info, exe_path)?;
+        } else {
+            match sub_menu_container_key.delete_subkey_all(sc_info.key_name) {
+                Ok(()) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Adds a single subcommand item under the main menu's "shell" subkey.
+fn add_sub_command(
+    sub_menu_container_key: &RegKey,
+    sc_info: &SubCommandInfo,
+    exe_path: &str,
+) -> Result<()> {
+    // Create the subcommand item key, e.g., HKCU\Software\Classes\*\shell\Zstd
+    // Tool\shell\CompressQuick
+    let (sub_command_entry_key, _) = sub_menu_container_key.create_subkey(sc_info.key_name)?;
+
+    // Set the display name for the subcommand item
+    sub_command_entry_key.set_value("MUIVerb", &sc_info.display_name)?;
+    // (Optional) Set an icon for the subcommand item
+    // sub_command_entry_key.set_value("Icon", &format!("\"{}\",0", exe_path))?;
+
+    // Create the command subkey to store the actual command to execute
+    let (command_key, _) = sub_command_entry_key.create_subkey("command")?;
+    let command_str = sc_info.arg_template.replace("{}", exe_path);
+    command_key.set_value("", &command_str)?; // Command string as the default value of the command key
+    Ok(())
+}
+
 /// Removes context menu entries.
 pub fn remove_context_menu_entries() -> Result<()> {
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
@@ -123,10 +227,22 @@ pub fn remove_context_menu_entries() -> Result<()> {
 
     // Since all subcommands are under the main menu item, simply recursively delete the main
     // menu item
-    let paths_to_delete = [FILE_SHELL_PATH, DIRECTORY_SHELL_PATH, FOLDER_SHELL_PATH];
+    let import_paths = IMPORT_EXTENSIONS
+        .iter()
+        .chain(&COMPRESSED_TAR_EXTENSIONS)
+        .map(|extension| format!("{FILE_ASSOCIATIONS_PATH}\\{extension}\\shell"));
+    let dwarfs_verbs = DWARFS_SUB_COMMANDS
+        .iter()
+        .map(|sc_info| format!("{DWARFS_SHELL_PATH}\\{}", sc_info.key_name));
+    let paths_to_delete = [FILE_SHELL_PATH, DIRECTORY_SHELL_PATH, FOLDER_SHELL_PATH]
+        .into_iter()
+        .map(String::from)
+        .chain(import_paths)
+        .map(|path_prefix| format!("{path_prefix}\\{MENU_NAME}"))
+        .chain(dwarfs_verbs);
     let mut removed_any = false;
-    for path_prefix in &paths_to_delete {
-        match classes_key.delete_subkey_all(format!("{path_prefix}\\{MENU_NAME}")) {
+    for path in paths_to_delete {
+        match classes_key.delete_subkey_all(path) {
             Ok(()) => removed_any = true,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
             Err(e) => return Err(e.into()),
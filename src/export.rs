@@ -0,0 +1,143 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Result, ensure};
+use clap::ValueEnum;
+
+use crate::{
+    compress::{PartialOutput, run_checked, run_with_progress, temp_dir, unpack_all},
+    conflict::{OnConflict, resolve_output},
+    options::ExtractFilter,
+    progress::{self, ProgressMode, status},
+};
+
+/// dwarfsextract 可以写出的归档格式（由其内置的 libarchive 生成）。
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// POSIX tar (pax)
+    Tar,
+    /// tar compressed with zstd
+    TarZst,
+    /// tar compressed with xz
+    TarXz,
+    /// zip
+    Zip,
+    /// cpio (SVR4 newc)
+    Cpio,
+}
+
+impl ArchiveFormat {
+    /// 按文件名后缀识别格式，不区分大小写。
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        [
+            (".tar", Self::Tar),
+            (".tar.zst", Self::TarZst),
+            (".tzst", Self::TarZst),
+            (".tar.xz", Self::TarXz),
+            (".txz", Self::TarXz),
+            (".zip", Self::Zip),
+            (".cpio", Self::Cpio),
+        ]
+        .into_iter()
+        .find_map(|(suffix, format)| name.ends_with(suffix).then_some(format))
+    }
+
+    /// 自动生成输出文件名时使用的后缀。
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
+            Self::Zip => "zip",
+            Self::Cpio => "cpio",
+        }
+    }
+
+    /// 对应的 dwarfsextract `--format`/`--format-filters` 参数。
+    fn to_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Tar => &["--format=pax"],
+            Self::TarZst => &["--format=pax", "--format-filters=zstd"],
+            Self::TarXz => &["--format=pax", "--format-filters=xz"],
+            Self::Zip => &["--format=zip"],
+            Self::Cpio => &["--format=newc"],
+        }
+    }
+}
+
+/// 把 dwarfs 文件转换为 tar/zip/cpio 归档。
+///
+/// `output_path` 为 `None` 时归档写到 stdout，此时提示信息只写到 stderr。写到文件时与压缩一样先写
+/// 临时文件，成功后才移到目标位置。
+pub fn export_dwarfs(
+    input_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    format: ArchiveFormat,
+    on_conflict: OnConflict,
+    filter: &ExtractFilter,
+) -> Result<()> {
+    let input_path = input_path.as_ref();
+    let filter_args = filter.to_args()?;
+    unpack_all()?;
+    ensure!(
+        input_path.is_file(),
+        "Input path is not a file: {}",
+        input_path.display()
+    );
+    let mut command = Command::new(temp_dir().join("dwarfsextract.exe"));
+    command
+        .arg("-i")
+        .arg(input_path)
+        .args(format.to_args())
+        .args(filter_args);
+
+    let Some(output_path) = output_path else {
+        // 不给 -o 时 dwarfsextract 把归档写到 stdout，不能再解析进度
+        eprintln!("Exporting {} to stdout", input_path.display());
+        return run_checked(command.stdin(Stdio::null()));
+    };
+    let Some(output_path) = resolve_output(output_path, on_conflict, true)? else {
+        return Ok(());
+    };
+    status(format_args!(
+        "Exporting {} to {}",
+        input_path.display(),
+        output_path.display()
+    ));
+    let partial = PartialOutput::new(&output_path)?;
+    command.arg("-o").arg(partial.path());
+    if progress::mode() != ProgressMode::Raw {
+        command.arg("--stdout-progress");
+    }
+    run_with_progress(&mut command)?;
+    partial.persist(on_conflict == OnConflict::Overwrite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_detected_from_extension() {
+        let detect = |name: &str| ArchiveFormat::from_path(Path::new(name));
+        assert!(detect("out/backup.tar") == Some(ArchiveFormat::Tar));
+        assert!(detect("backup.TAR.ZST") == Some(ArchiveFormat::TarZst));
+        assert!(detect("backup.txz") == Some(ArchiveFormat::TarXz));
+        assert!(detect("backup.zip") == Some(ArchiveFormat::Zip));
+        assert!(detect("backup.cpio") == Some(ArchiveFormat::Cpio));
+        assert!(detect("backup.dwarfs").is_none());
+        assert!(detect("backup.tar.gz").is_none());
+    }
+
+    #[test]
+    fn extension_round_trips_through_detection() {
+        for format in ArchiveFormat::value_variants() {
+            let name = format!("a.{}", format.extension());
+            assert!(ArchiveFormat::from_path(Path::new(&name)) == Some(*format));
+            assert!(format.to_args()[0].starts_with("--format="));
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! 不依赖 dwarfs.exe，直接读取 dwarfs 镜像的 section 和元数据，可以在任何平台上运行。
+//!
+//! 只支持 dir_entries 形式的元数据（镜像格式 2.3 及以后，即 dwarfs 0.5 以后生成的镜像），元数据
+//! section 须为未压缩或 zstd 压缩（mkdwarfs 的默认设置）。
+
+mod frozen;
+mod fsst;
+mod section;
+mod thrift;
+
+use std::{collections::HashSet, fs::File, io::BufReader, path::Path};
+
+use anyhow::{Context, Result, ensure};
+
+use self::frozen::{Schema, View};
+pub use self::section::{Section, SectionType};
+
+/// 文件类型位，与 `st_mode` 一致。
+pub const S_IFMT: u32 = 0o170_000;
+pub const S_IFDIR: u32 = 0o040_000;
+pub const S_IFREG: u32 = 0o100_000;
+pub const S_IFLNK: u32 = 0o120_000;
+
+/// 普通文件的一段数据在某个块中的位置。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub block: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InodeKind {
+    /// 按名字排序的子条目
+    Directory(Vec<DirEntry>),
+    File(Vec<Chunk>),
+    Symlink(String),
+    Device(u64),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inode {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Unix 时间戳（秒）
+    pub mtime: u64,
+    pub kind: InodeKind,
+}
+
+impl Inode {
+    /// 普通文件为数据大小，符号链接为目标的长度，其余为 0。
+    pub fn size(&self) -> u64 {
+        match &self.kind {
+            InodeKind::File(chunks) => chunks.iter().map(|chunk| u64::from(chunk.size)).sum(),
+            InodeKind::Symlink(target) => target.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// 解析后的文件系统元数据。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub block_size: u32,
+    pub total_fs_size: u64,
+    /// 修改时间的精度（秒）
+    pub time_resolution: u64,
+    pub inodes: Vec<Inode>,
+    pub root: u32,
+}
+
+/// 一个打开的 dwarfs 镜像。
+#[derive(Debug)]
+pub struct Image {
+    pub sections: Vec<Section>,
+    pub metadata: Metadata,
+}
+
+impl Image {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let sections = section::read_sections(&mut reader)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let find = |kind| {
+            sections
+                .iter()
+                .rfind(|section| section.kind == kind)
+                .with_context(|| format!("{} has no {} section", path.display(), kind.name()))
+        };
+        let schema = section::read_section(&mut reader, find(SectionType::MetadataSchema)?)?;
+        let data = section::read_section(&mut reader, find(SectionType::Metadata)?)?;
+        let schema = Schema::decode(&schema)?;
+        let metadata = Metadata::from_frozen(View::root(&schema, &data))
+            .with_context(|| format!("Invalid metadata in {}", path.display()))?;
+        Ok(Self { sections, metadata })
+    }
+}
+
+// metadata.thrift 中 `metadata` 结构体的字段编号
+mod field {
+    pub const CHUNKS: i16 = 1;
+    pub const DIRECTORIES: i16 = 2;
+    pub const INODES: i16 = 3;
+    pub const CHUNK_TABLE: i16 = 4;
+    pub const SYMLINK_TABLE: i16 = 6;
+    pub const UIDS: i16 = 7;
+    pub const GIDS: i16 = 8;
+    pub const MODES: i16 = 9;
+    pub const NAMES: i16 = 10;
+    pub const SYMLINKS: i16 = 11;
+    pub const TIMESTAMP_BASE: i16 = 12;
+    pub const BLOCK_SIZE: i16 = 15;
+    pub const TOTAL_FS_SIZE: i16 = 16;
+    pub const DEVICES: i16 = 17;
+    pub const OPTIONS: i16 = 18;
+    pub const DIR_ENTRIES: i16 = 19;
+    pub const SHARED_FILES_TABLE: i16 = 20;
+    pub const COMPACT_NAMES: i16 = 24;
+    pub const COMPACT_SYMLINKS: i16 = 25;
+}
+
+fn u32_list(view: &View, id: i16) -> Result<Vec<u32>> {
+    view.field(id)
+        .map_or_else(|| Ok(Vec::new()), |list| list.list()?.u32s())
+}
+
+/// 还原差分存储的序列。
+fn prefix_sums(values: &[u32]) -> Result<Vec<u32>> {
+    let mut sum = 0u32;
+    values
+        .iter()
+        .map(|&value| {
+            sum = sum.checked_add(value).context("Packed table overflows")?;
+            Ok(sum)
+        })
+        .collect()
+}
+
+/// 还原打包的共享文件表：每项为共享同一组数据块的 inode 数减 2。
+fn unpack_shared_files(packed: &[u32]) -> Vec<u32> {
+    packed
+        .iter()
+        .zip(0u32..)
+        .flat_map(|(&count, index)| std::iter::repeat_n(index, count as usize + 2))
+        .collect()
+}
+
+/// 解码 `string_table`：可能用 FSST 压缩，索引可能只存各字符串的长度。
+fn string_table(table: &View) -> Result<Vec<String>> {
+    let buffer = match table.field(1) {
+        Some(buffer) => buffer.bytes()?,
+        None => &[],
+    };
+    let decoder = table
+        .optional(2)
+        .map(|symtab| fsst::Decoder::new(symtab.bytes()?))
+        .transpose()?;
+    let index = u32_list(table, 3)?;
+    let offsets = if table.field_bool(4) {
+        std::iter::once(Ok(0))
+            .chain(prefix_sums(&index)?.into_iter().map(Ok))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        index
+    };
+    offsets
+        .windows(2)
+        .map(|range| {
+            let raw = buffer
+                .get(range[0] as usize..range[1] as usize)
+                .context("String table index exceeds its buffer")?;
+            let bytes = match &decoder {
+                Some(decoder) => decoder.decode(raw)?,
+                None => raw.to_vec(),
+            };
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        })
+        .collect()
+}
+
+/// 读取字符串列表，优先使用紧凑形式。
+fn strings(meta: &View, plain: i16, compact: i16) -> Result<Vec<String>> {
+    if let Some(table) = meta.optional(compact) {
+        return string_table(&table);
+    }
+    let Some(list) = meta.field(plain) else {
+        return Ok(Vec::new());
+    };
+    list.list()?
+        .iter()
+        .map(|item| Ok(String::from_utf8_lossy(item.bytes()?).into_owned()))
+        .collect()
+}
+
+/// 还原 inode 的修改时间：`时间精度 × (基准时间 + 偏移)`，损坏的元数据可能使其溢出。
+fn timestamp(resolution: u64, base: u64, offset: u64) -> Result<u64> {
+    base.checked_add(offset)
+        .and_then(|time| time.checked_mul(resolution))
+        .context("Invalid timestamp in metadata")
+}
+
+fn lookup<'t, T>(table: &'t [T], index: u64, what: &str) -> Result<&'t T> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| table.get(index))
+        .with_context(|| format!("Invalid {what} index {index}"))
+}
+
+impl Metadata {
+    fn from_frozen(meta: View) -> Result<Self> {
+        let options = meta.optional(field::OPTIONS);
+        let option = |id| options.is_some_and(|options| options.field_bool(id));
+        let time_resolution = options
+            .and_then(|options| options.optional(2))
+            .map_or(1, |resolution| resolution.uint());
+
+        let chunks = meta
+            .field(field::CHUNKS)
+            .map(|chunks| -> Result<Vec<Chunk>> {
+                chunks
+                    .list()?
+                    .iter()
+                    .map(|chunk| {
+                        Ok(Chunk {
+                            block: chunk.field_u32(1)?,
+                            offset: chunk.field_u32(2)?,
+                            size: chunk.field_u32(3)?,
+                        })
+                    })
+                    .collect()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let mut chunk_table = u32_list(&meta, field::CHUNK_TABLE)?;
+        if option(3) {
+            chunk_table = prefix_sums(&chunk_table)?;
+        }
+        let mut first_entries = Vec::new();
+        if let Some(directories) = meta.field(field::DIRECTORIES) {
+            for directory in directories.list()?.iter() {
+                first_entries.push(directory.field_u32(2)?);
+            }
+        }
+        if option(4) {
+            first_entries = prefix_sums(&first_entries)?;
+        }
+        let mut shared_files = meta
+            .optional(field::SHARED_FILES_TABLE)
+            .map(|table| table.list()?.u32s())
+            .transpose()?
+            .unwrap_or_default();
+        if option(5) {
+            shared_files = unpack_shared_files(&shared_files);
+        }
+        let symlink_table = u32_list(&meta, field::SYMLINK_TABLE)?;
+        let uids = u32_list(&meta, field::UIDS)?;
+        let gids = u32_list(&meta, field::GIDS)?;
+        let modes = u32_list(&meta, field::MODES)?;
+        let devices = meta
+            .optional(field::DEVICES)
+            .map(|devices| devices.list().map(|list| list.u64s()))
+            .transpose()?
+            .unwrap_or_default();
+        let names = strings(&meta, field::NAMES, field::COMPACT_NAMES)?;
+        let symlinks = strings(&meta, field::SYMLINKS, field::COMPACT_SYMLINKS)?;
+        let timestamp_base = meta.field_u64(field::TIMESTAMP_BASE);
+
+        let dir_entries = meta
+            .optional(field::DIR_ENTRIES)
+            .context(
+                "Metadata without dir_entries (created by dwarfs before 0.5) is not supported",
+            )?
+            .list()?;
+        let entry = |index: u32| -> Result<DirEntry> {
+            let entry = dir_entries
+                .get(index as usize)
+                .with_context(|| format!("Invalid directory entry {index}"))?;
+            Ok(DirEntry {
+                name: lookup(&names, entry.field_u64(1), "name")?.clone(),
+                inode: entry.field_u32(2)?,
+            })
+        };
+
+        // inode 按类型排列：文件夹、符号链接、普通文件（先独有后共享）、设备、其他
+        let directory_count = first_entries.len().saturating_sub(1);
+        let symlink_count = symlink_table.len();
+        let shared_chunk_lists = shared_files.iter().max().map_or(0, |&max| max as usize + 1);
+        let unique_files = chunk_table
+            .len()
+            .saturating_sub(1)
+            .checked_sub(shared_chunk_lists)
+            .context("Inconsistent shared files table")?;
+        let file_count = unique_files + shared_files.len();
+
+        let inode_list = meta
+            .field(field::INODES)
+            .context("Metadata has no inodes")?
+            .list()?;
+        let mut inodes = Vec::with_capacity(inode_list.len());
+        for (index, inode) in inode_list.iter().enumerate() {
+            let kind = if index < directory_count {
+                let (first, end) = (first_entries[index], first_entries[index + 1]);
+                ensure!(first <= end, "Invalid directory {index}");
+                InodeKind::Directory((first..end).map(entry).collect::<Result<_>>()?)
+            } else if index < directory_count + symlink_count {
+                let target = symlink_table[index - directory_count];
+                InodeKind::Symlink(
+                    symlinks
+                        .get(target as usize)
+                        .cloned()
+                        .with_context(|| format!("Invalid symlink index {target}"))?,
+                )
+            } else if index < directory_count + symlink_count + file_count {
+                let file = index - directory_count - symlink_count;
+                let list = if file < unique_files {
+                    file
+                } else {
+                    unique_files + shared_files[file - unique_files] as usize
+                };
+                let (first, end) = (chunk_table[list] as usize, chunk_table[list + 1] as usize);
+                InodeKind::File(
+                    chunks
+                        .get(first..end)
+                        .with_context(|| format!("Invalid chunk range of file {file}"))?
+                        .to_vec(),
+                )
+            } else {
+                let device = index - directory_count - symlink_count - file_count;
+                devices
+                    .get(device)
+                    .map_or(InodeKind::Other, |&id| InodeKind::Device(id))
+            };
+            inodes.push(Inode {
+                mode: *lookup(&modes, inode.field_u64(2), "mode")?,
+                uid: lookup(&uids, inode.field_u64(4), "uid").map_or(0, |&uid| uid),
+                gid: lookup(&gids, inode.field_u64(5), "gid").map_or(0, |&gid| gid),
+                mtime: timestamp(time_resolution, timestamp_base, inode.field_u64(7))?,
+                kind,
+            });
+        }
+
+        let root = entry(0)?.inode;
+        ensure!(
+            matches!(
+                inodes.get(root as usize).map(|inode| &inode.kind),
+                Some(InodeKind::Directory(_))
+            ),
+            "Root inode is not a directory"
+        );
+        Ok(Self {
+            block_size: meta.field_u32(field::BLOCK_SIZE)?,
+            total_fs_size: meta.field_u64(field::TOTAL_FS_SIZE),
+            time_resolution,
+            inodes,
+            root,
+        })
+    }
+
+    pub fn inode(&self, number: u32) -> Option<&Inode> {
+        self.inodes.get(number as usize)
+    }
+
+    /// 先序遍历所有条目（不含根目录），路径以 `/` 分隔，同一文件夹中按名字排序。
+    pub fn walk(&self, mut visit: impl FnMut(&str, &Inode)) {
+        let mut visited = HashSet::from([self.root]);
+        let mut stack = vec![(String::new(), self.root)];
+        while let Some((path, number)) = stack.pop() {
+            let Some(inode) = self.inode(number) else {
+                continue;
+            };
+            if number != self.root {
+                visit(&path, inode);
+            }
+            let InodeKind::Directory(entries) = &inode.kind else {
+                continue;
+            };
+            // 倒序入栈，使出栈顺序与名字顺序一致
+            for entry in entries.iter().rev() {
+                let is_dir = self
+                    .inode(entry.inode)
+                    .is_some_and(|inode| matches!(inode.kind, InodeKind::Directory(_)));
+                // 损坏的元数据中文件夹可能成环
+                if is_dir && !visited.insert(entry.inode) {
+                    continue;
+                }
+                let child = if path.is_empty() {
+                    entry.name.clone()
+                } else {
+                    format!("{path}/{}", entry.name)
+                };
+                stack.push((child, entry.inode));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{section::Compression, *};
+
+    fn fixture(name: &str) -> Image {
+        Image::open(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn walk(metadata: &Metadata) -> Vec<(String, u32, u64, u64)> {
+        let mut entries = Vec::new();
+        metadata.walk(|path, inode| {
+            entries.push((path.to_string(), inode.mode, inode.size(), inode.mtime));
+        });
+        entries
+    }
+
+    fn file_chunks(metadata: &Metadata, path: &str) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        metadata.walk(|entry, inode| {
+            if let (true, InodeKind::File(file)) = (entry == path, &inode.kind) {
+                chunks.clone_from(file);
+            }
+        });
+        chunks
+    }
+
+    /// 默认设置：普通的名字表，zstd 压缩，schema 中的布局和字段都以 map 存储。
+    #[test]
+    fn default_fixture_is_read() {
+        let image = fixture("default.dwarfs");
+        let kinds = image
+            .sections
+            .iter()
+            .map(|section| (section.kind, section.compression))
+            .collect::<Vec<_>>();
+        assert!(
+            kinds
+                == [
+                    (SectionType::Block, Compression::Zstd),
+                    (SectionType::MetadataSchema, Compression::Zstd),
+                    (SectionType::Metadata, Compression::Zstd),
+                    (SectionType::SectionIndex, Compression::None),
+                ]
+        );
+        let metadata = &image.metadata;
+        assert!(metadata.block_size == 1 << 24 && metadata.total_fs_size == 30);
+        let base = 1_700_000_000;
+        assert!(
+            walk(metadata)
+                == [
+                    ("docs".into(), 0o40755, 0, base + 200),
+                    ("docs/readme.txt".into(), 0o100_644, 15, base + 200),
+                    ("empty".into(), 0o40755, 0, base + 100),
+                    ("hello.txt".into(), 0o100_644, 15, base),
+                ]
+        );
+        assert!(
+            file_chunks(metadata, "hello.txt")
+                == [Chunk {
+                    block: 0,
+                    offset: 15,
+                    size: 15,
+                }]
+        );
+    }
+
+    /// 打包的文件夹、块表和共享文件表，FSST 压缩的名字，schema 中的布局和字段都以列表存储。
+    #[test]
+    fn packed_fixture_is_read() {
+        let image = fixture("packed.dwarfs");
+        assert!(
+            image
+                .sections
+                .iter()
+                .all(|section| section.compression == Compression::None)
+        );
+        let metadata = &image.metadata;
+        assert!(metadata.block_size == 1 << 16 && metadata.total_fs_size == 56);
+        assert!(metadata.time_resolution == 60);
+        // 时间精度为分钟
+        let base = 1_700_000_000 / 60 * 60;
+        assert!(
+            walk(metadata)
+                == [
+                    ("bin".into(), 0o40755, 0, base + 300),
+                    ("bin/tool".into(), 0o120_777, 14, base + 180),
+                    ("lib".into(), 0o40755, 0, base + 240),
+                    ("lib/copy-a.txt".into(), 0o100_644, 13, base),
+                    ("lib/copy-b.txt".into(), 0o100_644, 13, base),
+                    ("lib/tool.sh".into(), 0o100_755, 20, base + 120),
+                    ("notes.txt".into(), 0o100_644, 10, base + 60),
+                ]
+        );
+        let mut targets = Vec::new();
+        metadata.walk(|_, inode| {
+            if let InodeKind::Symlink(target) = &inode.kind {
+                targets.push((target.clone(), inode.uid, inode.gid));
+            }
+        });
+        assert!(targets == [("../lib/tool.sh".to_string(), 1000, 100)]);
+        let shared = file_chunks(metadata, "lib/copy-a.txt");
+        assert!(shared.len() == 1 && shared == file_chunks(metadata, "lib/copy-b.txt"));
+        let script = file_chunks(metadata, "lib/tool.sh");
+        assert!(script.len() == 2 && script[1] == file_chunks(metadata, "notes.txt")[0]);
+    }
+
+    #[test]
+    fn packed_tables_are_unpacked() {
+        assert!(prefix_sums(&[0, 2, 3, 1]).unwrap() == [0, 2, 5, 6]);
+        assert!(prefix_sums(&[u32::MAX, 1]).is_err());
+        assert!(unpack_shared_files(&[1, 0, 2]) == [0, 0, 0, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_rejected() {
+        assert!(timestamp(60, 28_333_333, 2).unwrap() == 1_700_000_100);
+        assert!(timestamp(1, u64::MAX, 1).is_err());
+        assert!(timestamp(60, u64::MAX / 2, 0).is_err());
+    }
+
+    #[test]
+    fn walk_visits_entries_in_order() {
+        let inode = |kind| Inode {
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            mtime: 0,
+            kind,
+        };
+        let entry = |name: &str, inode| DirEntry {
+            name: name.to_string(),
+            inode,
+        };
+        let metadata = Metadata {
+            block_size: 0,
+            total_fs_size: 0,
+            time_resolution: 1,
+            inodes: vec![
+                inode(InodeKind::Directory(vec![entry("a", 1), entry("b", 2)])),
+                // 指回根目录的坏条目不会导致死循环
+                inode(InodeKind::Directory(vec![entry("c", 2), entry("loop", 0)])),
+                inode(InodeKind::File(vec![Chunk {
+                    block: 0,
+                    offset: 0,
+                    size: 3,
+                }])),
+            ],
+            root: 0,
+        };
+        let mut paths = Vec::new();
+        metadata.walk(|path, inode| paths.push((path.to_string(), inode.size())));
+        assert!(paths == [("a".into(), 0), ("a/c".into(), 3), ("b".into(), 3)]);
+    }
+}
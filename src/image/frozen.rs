@@ -0,0 +1,362 @@
+//! thrift frozen2 格式的读取。dwarfs 的元数据以这种按位紧凑排列的格式存储，布局由单独的
+//! schema section 描述，可以直接在原始字节上按字段读取而无需反序列化。
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, ensure};
+
+use super::thrift::{self, Value};
+
+/// 结构体中的一个字段。`offset` 为正表示字节偏移，为负表示位偏移的相反数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub layout: i16,
+    pub offset: i16,
+}
+
+/// 一种类型的存储布局：按字节存储时 `size` 非零，否则占 `bits` 位。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub size: usize,
+    pub bits: usize,
+    pub fields: BTreeMap<i16, Field>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub layouts: BTreeMap<i16, Layout>,
+    pub root: i16,
+}
+
+// 列表、字符串和 optional 布局中固定的字段编号
+const LIST_DISTANCE: i16 = 1;
+const LIST_COUNT: i16 = 2;
+const LIST_ITEM: i16 = 3;
+const OPTIONAL_ISSET: i16 = 1;
+const OPTIONAL_VALUE: i16 = 2;
+
+fn int_field<T: TryFrom<i64>>(fields: &BTreeMap<i16, Value>, id: i16) -> Result<T> {
+    let value = fields.get(&id).and_then(Value::as_int).unwrap_or(0);
+    T::try_from(value)
+        .ok()
+        .with_context(|| format!("Invalid value {value} in frozen schema"))
+}
+
+fn decode_layout(value: &Value) -> Result<Layout> {
+    let fields = value
+        .as_struct()
+        .context("Invalid layout in frozen schema")?;
+    let mut layout = Layout {
+        size: int_field(fields, 1)?,
+        bits: int_field(fields, 2)?,
+        fields: BTreeMap::new(),
+    };
+    ensure!(layout.bits <= 64, "Invalid bit width in frozen schema");
+    for (id, field) in entries_by_id(fields.get(&3))? {
+        let field = field
+            .as_struct()
+            .context("Invalid field in frozen schema")?;
+        // 列表形式中没有对应字段的编号以空结构体占位
+        if !field.contains_key(&1) {
+            continue;
+        }
+        layout.fields.insert(id, Field {
+            layout: int_field(field, 1)?,
+            offset: int_field(field, 2)?,
+        });
+    }
+    Ok(layout)
+}
+
+/// 布局表和字段表可能是以编号为键的 map，也可能是以下标为编号的列表。
+fn entries_by_id(value: Option<&Value>) -> Result<Vec<(i16, &Value)>> {
+    match value {
+        Some(Value::Map(entries)) => entries
+            .iter()
+            .map(|(id, value)| {
+                let id = id.as_int().context("Invalid id in frozen schema")?;
+                Ok((i16::try_from(id)?, value))
+            })
+            .collect(),
+        Some(Value::List(items)) => items
+            .iter()
+            .enumerate()
+            .map(|(id, value)| Ok((i16::try_from(id)?, value)))
+            .collect(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+impl Schema {
+    /// 解码 compact 协议序列化的 schema。
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let fields = thrift::decode_struct(data).context("Invalid frozen schema")?;
+        let schema = Self {
+            layouts: entries_by_id(fields.get(&2))?
+                .into_iter()
+                .map(|(id, layout)| Ok((id, decode_layout(layout)?)))
+                .collect::<Result<_>>()?,
+            root: int_field(&fields, 3)?,
+        };
+        ensure!(
+            schema.layouts.contains_key(&schema.root),
+            "Frozen schema has no root layout"
+        );
+        Ok(schema)
+    }
+}
+
+/// 某个布局的值在数据中的位置：起始字节加上位偏移。
+#[derive(Debug, Clone, Copy)]
+pub struct View<'a> {
+    schema: &'a Schema,
+    data: &'a [u8],
+    layout: &'a Layout,
+    start: usize,
+    bit: usize,
+}
+
+/// 从 `bit` 位开始按小端读取 `bits` 位，超出数据的部分按 0 处理。
+fn read_bits(data: &[u8], bit: usize, bits: usize) -> u64 {
+    let mut value = 0u64;
+    let mut done = 0;
+    while done < bits {
+        let position = bit + done;
+        let byte = data.get(position / 8).copied().unwrap_or(0);
+        let shift = position % 8;
+        let take = (8 - shift).min(bits - done);
+        let chunk = (u64::from(byte) >> shift) & ((1u64 << take) - 1);
+        value |= chunk << done;
+        done += take;
+    }
+    value
+}
+
+impl<'a> View<'a> {
+    pub fn root(schema: &'a Schema, data: &'a [u8]) -> Self {
+        Self {
+            schema,
+            data,
+            layout: &schema.layouts[&schema.root],
+            start: 0,
+            bit: 0,
+        }
+    }
+
+    /// 结构体字段；schema 中没有该字段（值总为默认值）时返回 `None`。
+    pub fn field(&self, id: i16) -> Option<Self> {
+        let field = self.layout.fields.get(&id)?;
+        let layout = self.schema.layouts.get(&field.layout)?;
+        let offset = usize::from(field.offset.unsigned_abs());
+        let (start, bit) = if field.offset >= 0 {
+            (self.start + offset, self.bit)
+        } else {
+            (self.start, self.bit + offset)
+        };
+        Some(Self {
+            layout,
+            start,
+            bit,
+            ..*self
+        })
+    }
+
+    pub fn uint(&self) -> u64 {
+        // 整数只用 bits 描述宽度；按字节存储的布局不会是整数
+        read_bits(self.data, self.start * 8 + self.bit, self.layout.bits)
+    }
+
+    pub fn field_u64(&self, id: i16) -> u64 {
+        self.field(id).map_or(0, |field| field.uint())
+    }
+
+    pub fn field_u32(&self, id: i16) -> Result<u32> {
+        let value = self.field_u64(id);
+        u32::try_from(value).with_context(|| format!("Value {value} of field {id} is too large"))
+    }
+
+    pub fn field_bool(&self, id: i16) -> bool {
+        self.field_u64(id) != 0
+    }
+
+    /// 读取 optional 字段，未设置时返回 `None`。
+    pub fn optional(&self, id: i16) -> Option<Self> {
+        let optional = self.field(id)?;
+        optional
+            .field_bool(OPTIONAL_ISSET)
+            .then(|| optional.field(OPTIONAL_VALUE))
+            .flatten()
+    }
+
+    /// 数据区的起始字节和元素个数。
+    fn extent(&self) -> Result<(usize, usize)> {
+        let count = usize::try_from(self.field_u64(LIST_COUNT))?;
+        if count == 0 {
+            return Ok((0, 0));
+        }
+        let distance = usize::try_from(self.field_u64(LIST_DISTANCE))?;
+        let start = self
+            .start
+            .checked_add(distance)
+            .context("Invalid list position in frozen data")?;
+        Ok((start, count))
+    }
+
+    pub fn list(&self) -> Result<List<'a>> {
+        let (start, count) = self.extent()?;
+        let item = self
+            .layout
+            .fields
+            .get(&LIST_ITEM)
+            .and_then(|field| self.schema.layouts.get(&field.layout))
+            .unwrap_or(&EMPTY_LAYOUT);
+        let stride = if item.size > 0 {
+            item.size * 8
+        } else {
+            item.bits
+        };
+        let end_bit = count
+            .checked_mul(stride)
+            .and_then(|bits| bits.checked_add(start * 8))
+            .context("Invalid list size in frozen data")?;
+        ensure!(
+            end_bit <= self.data.len() * 8 || stride == 0,
+            "List exceeds frozen data"
+        );
+        Ok(List {
+            schema: self.schema,
+            data: self.data,
+            item,
+            start,
+            count,
+        })
+    }
+
+    pub fn bytes(&self) -> Result<&'a [u8]> {
+        let (start, count) = self.extent()?;
+        start
+            .checked_add(count)
+            .and_then(|end| self.data.get(start..end))
+            .context("String exceeds frozen data")
+    }
+}
+
+/// 所有值都为默认值的类型不占空间，schema 中会省略它的布局。
+static EMPTY_LAYOUT: Layout = Layout {
+    size: 0,
+    bits: 0,
+    fields: BTreeMap::new(),
+};
+
+/// 列表的视图，元素按字节或按位连续排列。
+pub struct List<'a> {
+    schema: &'a Schema,
+    data: &'a [u8],
+    item: &'a Layout,
+    start: usize,
+    count: usize,
+}
+
+impl<'a> List<'a> {
+    pub const fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn get(&self, index: usize) -> Option<View<'a>> {
+        let layout = self.item;
+        (index < self.count).then(|| {
+            let (start, bit) = if layout.size > 0 {
+                (self.start + index * layout.size, 0)
+            } else {
+                (self.start, index * layout.bits)
+            };
+            View {
+                schema: self.schema,
+                data: self.data,
+                layout,
+                start,
+                bit,
+            }
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = View<'a>> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    pub fn u64s(&self) -> Vec<u64> {
+        self.iter().map(|item| item.uint()).collect()
+    }
+
+    pub fn u32s(&self) -> Result<Vec<u32>> {
+        self.iter()
+            .map(|item| Ok(u32::try_from(item.uint())?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 根结构体有两个字段：按位排列的 u32 列表（每项 5 位）和一个字符串。
+    fn test_schema() -> Schema {
+        let int = |bits| Layout {
+            bits,
+            ..Layout::default()
+        };
+        let field = |layout, offset| Field { layout, offset };
+        Schema {
+            layouts: BTreeMap::from([
+                (0, Layout {
+                    size: 4,
+                    bits: 0,
+                    fields: BTreeMap::from([(1, field(1, -0)), (2, field(2, 2))]),
+                }),
+                (1, Layout {
+                    size: 0,
+                    bits: 8,
+                    fields: BTreeMap::from([
+                        (LIST_DISTANCE, field(3, 0)),
+                        (LIST_COUNT, field(3, -4)),
+                        (LIST_ITEM, field(4, 0)),
+                    ]),
+                }),
+                (2, Layout {
+                    size: 2,
+                    bits: 0,
+                    fields: BTreeMap::from([
+                        (LIST_DISTANCE, field(5, 0)),
+                        (LIST_COUNT, field(5, 1)),
+                    ]),
+                }),
+                (3, int(4)),
+                (4, int(5)),
+                (5, int(8)),
+            ]),
+            root: 0,
+        }
+    }
+
+    #[test]
+    fn bits_are_read_little_endian_across_bytes() {
+        assert!(read_bits(&[0b1010_0000, 0b0000_0011], 5, 5) == 0b11101);
+        assert!(read_bits(&[0xff], 4, 8) == 0x0f);
+        assert!(read_bits(&[], 0, 0) == 0);
+    }
+
+    #[test]
+    fn lists_and_strings_are_viewed_in_place() {
+        let schema = test_schema();
+        // 字节 0：列表 distance=4、count=3；字节 2-3：字符串 distance=5(相对字节 2)、count=2
+        // 字节 4-5：3 个 5 位整数 1, 2, 31；字节 7-8："hi"
+        let items: u16 = 1 | (2 << 5) | (31 << 10);
+        let [low, high] = items.to_le_bytes();
+        let data = [0x34, 0, 5, 2, low, high, 0, b'h', b'i'];
+        let root = View::root(&schema, &data);
+        let list = root.field(1).unwrap().list().unwrap();
+        assert!(list.u64s() == [1, 2, 31]);
+        assert!(root.field(2).unwrap().bytes().unwrap() == b"hi");
+        assert!(root.field(3).is_none());
+    }
+}
@@ -0,0 +1,82 @@
+//! FSST 字符串压缩的解码。dwarfs 用它压缩文件名和符号链接目标。
+
+use anyhow::{Result, ensure};
+
+/// 编码中表示“下一个字节按原样输出”的转义码。
+const ESCAPE: u8 = 255;
+
+/// 由 `fsst_export` 导出的符号表重建的解码器。
+pub struct Decoder {
+    symbols: Vec<Vec<u8>>,
+}
+
+impl Decoder {
+    /// 解析导出的符号表：8 字节版本号、1 字节 zero-terminated 标志、8 字节各长度符号数，
+    /// 随后按长度 2..=8、1 的顺序排列所有符号。
+    pub fn new(table: &[u8]) -> Result<Self> {
+        ensure!(table.len() >= 17, "FSST symbol table is too short");
+        let zero_terminated = table[8] & 1 == 1;
+        let mut histogram = [0usize; 8];
+        for (count, &byte) in histogram.iter_mut().zip(&table[9..17]) {
+            *count = usize::from(byte);
+        }
+        let mut symbols = Vec::new();
+        if zero_terminated {
+            // 编码 0 固定为单字节 0，不在符号表中存储
+            symbols.push(vec![0]);
+            ensure!(histogram[0] > 0, "Invalid FSST symbol table");
+            histogram[0] -= 1;
+        }
+        let mut position = 17;
+        for length in 1..=8 {
+            let symbol_length = length % 8 + 1;
+            for _ in 0..histogram[length % 8] {
+                let symbol = table.get(position..position + symbol_length);
+                ensure!(symbol.is_some(), "FSST symbol table is truncated");
+                symbols.extend(symbol.map(<[u8]>::to_vec));
+                position += symbol_length;
+            }
+        }
+        ensure!(
+            symbols.len() < usize::from(ESCAPE),
+            "FSST symbol table has too many symbols"
+        );
+        Ok(Self { symbols })
+    }
+
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::with_capacity(encoded.len() * 3);
+        let mut bytes = encoded.iter();
+        while let Some(&code) = bytes.next() {
+            if code == ESCAPE {
+                let literal = bytes.next();
+                ensure!(literal.is_some(), "Truncated FSST escape sequence");
+                decoded.extend(literal);
+            } else {
+                let symbol = self.symbols.get(usize::from(code));
+                ensure!(symbol.is_some(), "Unknown FSST code {code}");
+                decoded.extend(symbol.into_iter().flatten());
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_are_ordered_by_length_with_single_bytes_last() {
+        let mut table = vec![0; 8];
+        table.push(0); // 非 zero-terminated
+        // 单字节符号 1 个，2 字节 1 个，3 字节 1 个
+        table.extend([1, 1, 1, 0, 0, 0, 0, 0]);
+        table.extend(b"abcdex");
+        let decoder = Decoder::new(&table).unwrap();
+        // 编码 0 = "ab"，1 = "cde"，2 = "x"
+        assert!(decoder.decode(&[0, 2, 1, ESCAPE, b'!']).unwrap() == b"abxcde!");
+        assert!(decoder.decode(&[3]).is_err());
+        assert!(decoder.decode(&[ESCAPE]).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! dwarfs 镜像的 section 结构：每个 section 以 64 字节的头开始，随后是（压缩后的）数据。
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::{Context, Result, bail, ensure};
+
+const MAGIC: &[u8; 6] = b"DWARFS";
+const HEADER_SIZE: usize = 64;
+/// 查找镜像起始位置时最多扫描的字节数（镜像前可能附加了其他数据）。
+const MAX_IMAGE_OFFSET: usize = 1 << 20;
+
+/// section 的类型。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionType {
+    Block,
+    MetadataSchema,
+    Metadata,
+    SectionIndex,
+    History,
+    Unknown(u16),
+}
+
+impl SectionType {
+    const fn from_raw(raw: u16) -> Self {
+        match raw {
+            0 => Self::Block,
+            7 => Self::MetadataSchema,
+            8 => Self::Metadata,
+            9 => Self::SectionIndex,
+            10 => Self::History,
+            other => Self::Unknown(other),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Self::Block => "BLOCK".to_string(),
+            Self::MetadataSchema => "METADATA_V2_SCHEMA".to_string(),
+            Self::Metadata => "METADATA_V2".to_string(),
+            Self::SectionIndex => "SECTION_INDEX".to_string(),
+            Self::History => "HISTORY".to_string(),
+            Self::Unknown(raw) => format!("UNKNOWN({raw})"),
+        }
+    }
+}
+
+/// section 数据使用的压缩算法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lzma,
+    Zstd,
+    Lz4,
+    Lz4hc,
+    Brotli,
+    Flac,
+    Ricepp,
+    Unknown(u16),
+}
+
+impl Compression {
+    const fn from_raw(raw: u16) -> Self {
+        match raw {
+            0 => Self::None,
+            1 => Self::Lzma,
+            2 => Self::Zstd,
+            3 => Self::Lz4,
+            4 => Self::Lz4hc,
+            5 => Self::Brotli,
+            6 => Self::Flac,
+            7 => Self::Ricepp,
+            other => Self::Unknown(other),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Self::None => "NONE".to_string(),
+            Self::Lzma => "LZMA".to_string(),
+            Self::Zstd => "ZSTD".to_string(),
+            Self::Lz4 => "LZ4".to_string(),
+            Self::Lz4hc => "LZ4HC".to_string(),
+            Self::Brotli => "BROTLI".to_string(),
+            Self::Flac => "FLAC".to_string(),
+            Self::Ricepp => "RICEPP".to_string(),
+            Self::Unknown(raw) => format!("UNKNOWN({raw})"),
+        }
+    }
+}
+
+/// 一个 section 的头信息。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub major: u8,
+    pub minor: u8,
+    pub number: u32,
+    pub kind: SectionType,
+    pub compression: Compression,
+    /// 数据在文件中的位置和（压缩后的）长度
+    pub offset: u64,
+    pub length: u64,
+}
+
+fn parse_header(header: &[u8; HEADER_SIZE], position: u64) -> Result<Section> {
+    ensure!(
+        &header[..6] == MAGIC,
+        "Invalid section header at offset {position}"
+    );
+    let (major, minor) = (header[6], header[7]);
+    ensure!(
+        major == 2,
+        "Unsupported dwarfs image version {major}.{minor}"
+    );
+    // 8..40 为 SHA-512/256，40..48 为 XXH3-64，这里不校验
+    let u16_at = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+    Ok(Section {
+        major,
+        minor,
+        number: u32::from_le_bytes(header[48..52].try_into()?),
+        kind: SectionType::from_raw(u16_at(52)),
+        compression: Compression::from_raw(u16_at(54)),
+        offset: position + HEADER_SIZE as u64,
+        length: u64::from_le_bytes(header[56..64].try_into()?),
+    })
+}
+
+/// 在文件开头查找第一个 section 头，返回镜像的起始偏移。
+fn find_image_offset(reader: &mut (impl Read + Seek)) -> Result<u64> {
+    let mut head = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader
+        .take((MAX_IMAGE_OFFSET + HEADER_SIZE) as u64)
+        .read_to_end(&mut head)?;
+    head.windows(8)
+        .position(|window| &window[..6] == MAGIC && window[6] == 2)
+        .map(|position| position as u64)
+        .context("Not a dwarfs image: no section header found")
+}
+
+/// 依次读取全部 section 头，不读取数据。
+pub fn read_sections(reader: &mut (impl Read + Seek)) -> Result<Vec<Section>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut position = find_image_offset(reader)?;
+    let mut sections = Vec::new();
+    while position < end {
+        let mut header = [0; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(position))?;
+        reader
+            .read_exact(&mut header)
+            .with_context(|| format!("Truncated section header at offset {position}"))?;
+        let section = parse_header(&header, position)?;
+        position = section
+            .offset
+            .checked_add(section.length)
+            .filter(|&next| next <= end)
+            .with_context(|| format!("Section {} exceeds the image", section.number))?;
+        sections.push(section);
+    }
+    Ok(sections)
+}
+
+/// 读取并解压 section 的数据。
+pub fn read_section(reader: &mut (impl Read + Seek), section: &Section) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(section.offset))?;
+    let mut data = Vec::new();
+    reader.take(section.length).read_to_end(&mut data)?;
+    match section.compression {
+        Compression::None => Ok(data),
+        Compression::Zstd => zstd::decode_all(io::Cursor::new(data))
+            .with_context(|| format!("Failed to decompress section {}", section.number)),
+        other => bail!(
+            "Section {} uses {} compression, which can't be read natively",
+            section.number,
+            other.name()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// 构造一个 section：头 + 数据。
+    fn section_bytes(number: u32, kind: u16, compression: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::from(*MAGIC);
+        bytes.extend([2, 5]);
+        bytes.extend([0; 40]);
+        bytes.extend(number.to_le_bytes());
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend(compression.to_le_bytes());
+        bytes.extend((data.len() as u64).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn sections_are_read_after_a_leading_header() {
+        let mut image = b"#!/bin/sh\n".to_vec();
+        image.extend(section_bytes(0, 0, 0, b"block"));
+        image.extend(section_bytes(
+            1,
+            8,
+            2,
+            &zstd::encode_all(&b"metadata"[..], 3).unwrap(),
+        ));
+        let mut reader = Cursor::new(image);
+        let sections = read_sections(&mut reader).unwrap();
+        assert!(sections.len() == 2);
+        assert!(sections[0].offset == 10 + HEADER_SIZE as u64 && sections[0].length == 5);
+        assert!(sections[1].kind == SectionType::Metadata);
+        assert!(sections[1].compression == Compression::Zstd);
+        assert!(read_section(&mut reader, &sections[1]).unwrap() == b"metadata");
+    }
+
+    #[test]
+    fn truncated_image_is_rejected() {
+        let mut image = section_bytes(0, 0, 0, b"block");
+        image.truncate(image.len() - 1);
+        assert!(read_sections(&mut Cursor::new(image)).is_err());
+        assert!(read_sections(&mut Cursor::new(b"not an image".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Thrift compact 协议的通用解码，只用于读取 frozen 元数据的 schema。
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, bail, ensure};
+
+/// 解码后的 thrift 值。整数统一为 `i64`。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Struct(BTreeMap<i16, Value>),
+}
+
+impl Value {
+    pub const fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub const fn as_struct(&self) -> Option<&BTreeMap<i16, Self>> {
+        match self {
+            Self::Struct(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+// compact 协议的类型编号
+const STOP: u8 = 0;
+const BOOL_TRUE: u8 = 1;
+const BOOL_FALSE: u8 = 2;
+const BYTE: u8 = 3;
+const I16: u8 = 4;
+const I32: u8 = 5;
+const I64: u8 = 6;
+const DOUBLE: u8 = 7;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const SET: u8 = 10;
+const MAP: u8 = 11;
+const STRUCT: u8 = 12;
+
+/// 嵌套层数上限，防止损坏的数据导致栈溢出。
+const MAX_DEPTH: usize = 64;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .context("Unexpected end of thrift data")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Invalid varint in thrift data")
+    }
+
+    fn zigzag(&mut self) -> Result<i64> {
+        let value = self.varint()?;
+        #[allow(clippy::cast_possible_wrap)]
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        let length = usize::try_from(self.varint()?)?;
+        ensure!(
+            length <= self.data.len() - self.pos.min(self.data.len()),
+            "Thrift length exceeds data"
+        );
+        Ok(length)
+    }
+
+    fn value(&mut self, kind: u8, depth: usize) -> Result<Value> {
+        ensure!(depth < MAX_DEPTH, "Thrift data is nested too deeply");
+        Ok(match kind {
+            BOOL_TRUE => Value::Bool(true),
+            BOOL_FALSE => Value::Bool(false),
+            BYTE => Value::Int(i64::from(i8::from_le_bytes([self.byte()?]))),
+            I16 | I32 | I64 => Value::Int(self.zigzag()?),
+            DOUBLE => {
+                let bytes = self
+                    .data
+                    .get(self.pos..self.pos + 8)
+                    .context("Unexpected end of thrift data")?;
+                self.pos += 8;
+                Value::Double(f64::from_le_bytes(bytes.try_into()?))
+            },
+            BINARY => {
+                let length = self.length()?;
+                let bytes = self.data[self.pos..self.pos + length].to_vec();
+                self.pos += length;
+                Value::Binary(bytes)
+            },
+            LIST | SET => {
+                let header = self.byte()?;
+                let size = match header >> 4 {
+                    15 => self.length()?,
+                    size => usize::from(size),
+                };
+                let element = header & 0x0f;
+                let mut items = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    items.push(self.collection_item(element, depth)?);
+                }
+                Value::List(items)
+            },
+            MAP => {
+                let size = self.length()?;
+                let mut entries = Vec::with_capacity(size.min(1024));
+                if size > 0 {
+                    let types = self.byte()?;
+                    for _ in 0..size {
+                        let key = self.collection_item(types >> 4, depth)?;
+                        let value = self.collection_item(types & 0x0f, depth)?;
+                        entries.push((key, value));
+                    }
+                }
+                Value::Map(entries)
+            },
+            STRUCT => self.fields(depth + 1)?,
+            _ => bail!("Unknown thrift type {kind}"),
+        })
+    }
+
+    /// 集合中的 bool 以单独的字节存储。
+    fn collection_item(&mut self, kind: u8, depth: usize) -> Result<Value> {
+        if kind == BOOL_TRUE || kind == BOOL_FALSE {
+            return Ok(Value::Bool(self.byte()? == BOOL_TRUE));
+        }
+        self.value(kind, depth + 1)
+    }
+
+    fn fields(&mut self, depth: usize) -> Result<Value> {
+        let mut fields = BTreeMap::new();
+        let mut last_id = 0i16;
+        loop {
+            let header = self.byte()?;
+            let kind = header & 0x0f;
+            if kind == STOP {
+                return Ok(Value::Struct(fields));
+            }
+            let id = match header >> 4 {
+                0 => i16::try_from(self.zigzag()?)?,
+                delta => last_id + i16::from(delta),
+            };
+            last_id = id;
+            fields.insert(id, self.value(kind, depth)?);
+        }
+    }
+}
+
+/// 解码一个 compact 协议序列化的结构体。
+pub fn decode_struct(data: &[u8]) -> Result<BTreeMap<i16, Value>> {
+    match (Reader { data, pos: 0 }).fields(0)? {
+        Value::Struct(fields) => Ok(fields),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_struct_is_decoded() {
+        let data = [
+            0x15, 0x0e, // field 1, i32 = 7
+            0x28, 0x02, b'h', b'i', // field 3 (delta 2), binary "hi"
+            0x19, 0x25, 0x02, 0x04, // field 4, list<i32> [1, 2]
+            0x0b, 0x28, 0x01, 0x45, 0x02, 0x02, // field 20 (long form), map<i16,i32> {1: 1}
+            0x00,
+        ];
+        let fields = decode_struct(&data).unwrap();
+        assert!(fields[&1] == Value::Int(7));
+        assert!(fields[&3] == Value::Binary(b"hi".to_vec()));
+        assert!(fields[&4] == Value::List(vec![Value::Int(1), Value::Int(2)]));
+        assert!(fields[&20] == Value::Map(vec![(Value::Int(1), Value::Int(1))]));
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        assert!(decode_struct(&[0x15]).is_err());
+        assert!(decode_struct(&[0x18, 0x05, b'a']).is_err());
+    }
+}
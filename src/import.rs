@@ -0,0 +1,134 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, ensure};
+
+use crate::{
+    PathExt,
+    compress::{CompressBehavior, compress_folder_to_resolved, parent_dir, run_checked},
+    conflict::resolve_output,
+    options::CompressOptions,
+    progress::status,
+};
+
+/// 能够导入的归档后缀，较长的写在前面以便 `.tar.gz` 优先于 `.gz` 被剥离。
+const ARCHIVE_SUFFIXES: [&str; 10] = [
+    ".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst", ".tgz", ".tbz2", ".txz", ".tzst", ".tar", ".zip",
+];
+
+/// Windows 10 起自带的 bsdtar，可以解压 zip 和各种压缩的 tar，并保留修改时间。
+fn tar_exe() -> PathBuf {
+    env::var_os("SystemRoot")
+        .map(|root| PathBuf::from(root).join("System32").join("tar.exe"))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from("tar.exe"))
+}
+
+/// 文件名的归档后缀（不区分大小写），不是能导入的归档时返回 `None`。
+fn archive_suffix(name: &str) -> Option<&'static str> {
+    let lowercase = name.to_lowercase();
+    ARCHIVE_SUFFIXES
+        .into_iter()
+        .find(|suffix| lowercase.len() > suffix.len() && lowercase.ends_with(suffix))
+}
+
+/// 导入后默认的 .dwarfs 路径：去掉归档后缀（不区分大小写）再加上 `.dwarfs`。
+pub fn default_import_path(input_path: &Path) -> PathBuf {
+    let name = input_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let stem =
+        archive_suffix(&name).map_or(name.as_str(), |suffix| &name[..name.len() - suffix.len()]);
+    input_path.with_file_name(stem).add_ext()
+}
+
+/// 把 zip/tar 归档转换为 .dwarfs 文件。
+///
+/// 归档先被解压到输出文件旁的一个临时文件夹，再交给 [`compress_folder_to_resolved`]，临时文件夹在
+/// 结束时（包括出错时）删除。源归档不会被删除，因此不支持 [`CompressBehavior::delete_source`]。
+pub fn import_archive_to_dwarfs(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    options: &CompressOptions,
+    behavior: CompressBehavior,
+) -> Result<()> {
+    let input_path = input_path.as_ref();
+    ensure!(
+        behavior.delete_source.is_none(),
+        "Deleting the source is not supported when importing an archive"
+    );
+    options.validate()?;
+    ensure!(
+        input_path.is_file(),
+        "Input path is not a file: {}",
+        input_path.display()
+    );
+    // 单独压缩的文件（如 `notes.txt.gz`）tar 也能“解压”，但得到的不是归档里的内容
+    ensure!(
+        archive_suffix(&input_path.file_name().unwrap_or_default().to_string_lossy()).is_some(),
+        "Not a zip or tar archive: {} (supported: {})",
+        input_path.display(),
+        ARCHIVE_SUFFIXES.join(", ")
+    );
+    // 先处理输出冲突，避免跳过时白白解压一遍
+    let Some(output_path) = resolve_output(output_path.as_ref(), behavior.on_conflict, true)?
+    else {
+        return Ok(());
+    };
+
+    // 解压到输出所在的磁盘，而不是可能空间不足的系统临时目录
+    let staging = tempfile::Builder::new()
+        .prefix(".dwarfs-import-")
+        .tempdir_in(parent_dir(&output_path))?;
+    status(format_args!(
+        "Extracting {} to {}",
+        input_path.display(),
+        staging.path().display()
+    ));
+    run_checked(
+        Command::new(tar_exe())
+            .arg("-xf")
+            .arg(input_path)
+            .arg("-C")
+            .arg(staging.path())
+            .stdin(Stdio::null()),
+    )
+    .with_context(|| format!("Failed to extract {}", input_path.display()))?;
+    compress_folder_to_resolved(staging.path(), &output_path, options, behavior)?;
+    staging
+        .close()
+        .context("Failed to remove the temporary import folder")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_import_path_strips_archive_suffix() {
+        let path = |name: &str| default_import_path(Path::new(name));
+        assert!(path("dir/photos.zip") == Path::new("dir/photos.dwarfs"));
+        assert!(path("src.TAR.GZ") == Path::new("src.dwarfs"));
+        assert!(path("v1.2.tgz") == Path::new("v1.2.dwarfs"));
+        assert!(path("data.tar.zst") == Path::new("data.dwarfs"));
+    }
+
+    #[test]
+    fn only_zip_and_tar_archives_are_importable() {
+        assert!(archive_suffix("a.ZIP") == Some(".zip"));
+        assert!(archive_suffix("a.tar.xz") == Some(".tar.xz"));
+        assert!(archive_suffix("notes.txt.gz").is_none());
+        assert!(archive_suffix("disk.xz").is_none());
+    }
+
+    #[test]
+    fn unknown_suffix_is_kept() {
+        assert!(default_import_path(Path::new("notes.7z")) == Path::new("notes.7z.dwarfs"));
+        assert!(default_import_path(Path::new(".zip")) == Path::new(".zip.dwarfs"));
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, ensure};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    compress::{temp_dir, unpack_all},
+    image::{Image, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG},
+    progress::format_size,
+};
+
+/// 归档的概要信息，`--json` 时原样序列化输出。dwarfsck 没有给出的项为 `None`。
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub path: PathBuf,
+    pub image_version: Option<String>,
+    pub created_by: Option<String>,
+    pub block_size: Option<u64>,
+    pub block_count: Option<u64>,
+    /// 打包前所有文件的总大小
+    pub original_size: Option<u64>,
+    /// .dwarfs 文件本身的大小
+    pub compressed_size: u64,
+    /// 每种 section 使用的压缩算法，例如 `BLOCK` -> `{"ZSTD"}`
+    pub section_compression: BTreeMap<String, BTreeSet<String>>,
+    pub files: Option<u64>,
+    pub directories: Option<u64>,
+    pub symlinks: Option<u64>,
+    /// 创建和每次重新压缩时的命令行参数，取自镜像的历史记录
+    pub creation_options: Vec<Vec<String>>,
+}
+
+/// 依次尝试多个 JSON 指针，返回第一个存在的值。不同版本的 dwarfsck 字段名略有差异。
+fn lookup<'a>(value: &'a Value, pointers: &[&str]) -> Option<&'a Value> {
+    pointers.iter().find_map(|pointer| value.pointer(pointer))
+}
+
+fn lookup_u64(value: &Value, pointers: &[&str]) -> Option<u64> {
+    lookup(value, pointers).and_then(Value::as_u64)
+}
+
+fn value_to_string(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.to_string(), str::to_string)
+}
+
+/// 从 `dwarfsck --json` 的输出中取出概要信息。
+fn parse_fsinfo(info: &Value, archive: &mut ArchiveInfo) {
+    archive.image_version = lookup(info, &["/version", "/fs_version"]).map(|version| {
+        match (version.get("major"), version.get("minor")) {
+            (Some(major), Some(minor)) => format!("{major}.{minor}"),
+            _ => value_to_string(version),
+        }
+    });
+    archive.created_by = lookup(info, &["/created_by"]).map(value_to_string);
+    archive.block_size = lookup_u64(info, &["/block_size"]);
+    archive.block_count = lookup_u64(info, &["/block_count"]);
+    archive.original_size = lookup_u64(info, &[
+        "/original_filesystem_size",
+        "/original_size",
+        "/total_fs_size",
+    ]);
+    if let Some(sections) = lookup(info, &["/sections"]).and_then(Value::as_array) {
+        for section in sections {
+            if let (Some(kind), Some(compression)) = (
+                section.get("type").map(value_to_string),
+                section.get("compression").map(value_to_string),
+            ) {
+                archive
+                    .section_compression
+                    .entry(kind)
+                    .or_default()
+                    .insert(compression);
+            }
+        }
+    }
+    if let Some(history) = lookup(info, &["/history"]).and_then(Value::as_array) {
+        archive.creation_options = history
+            .iter()
+            .filter_map(|entry| entry.get("arguments")?.as_array())
+            .map(|arguments| arguments.iter().map(value_to_string).collect())
+            .collect();
+    }
+}
+
+/// 按 inode 的 mode 统计文件、文件夹和符号链接数。
+fn tally_modes(modes: impl IntoIterator<Item = u32>) -> (u64, u64, u64) {
+    let (mut files, mut directories, mut symlinks) = (0, 0, 0);
+    for mode in modes {
+        match mode & S_IFMT {
+            S_IFREG => files += 1,
+            S_IFDIR => directories += 1,
+            S_IFLNK => symlinks += 1,
+            _ => {},
+        }
+    }
+    (files, directories, symlinks)
+}
+
+/// 从 `dwarfsck --export-metadata` 导出的元数据中统计 inode。
+fn count_inodes(metadata: &Value) -> Option<(u64, u64, u64)> {
+    let modes = metadata.get("modes")?.as_array()?;
+    let inode_modes = metadata
+        .get("inodes")?
+        .as_array()?
+        .iter()
+        .map(|inode| {
+            let index = usize::try_from(inode.get("mode_index")?.as_u64()?).ok()?;
+            u32::try_from(modes.get(index)?.as_u64()?).ok()
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(tally_modes(inode_modes))
+}
+
+/// 用 dwarfsck 导出元数据再统计 inode，用于无法直接读取的镜像。
+fn count_inodes_with_dwarfsck(image: &Path) -> Result<Option<(u64, u64, u64)>> {
+    let export = tempfile::Builder::new()
+        .suffix(".json")
+        .tempfile_in(temp_dir())?
+        .into_temp_path();
+    let export_arg = format!("--export-metadata={}", export.display());
+    dwarfsck(&["--no-check", export_arg.as_str()], image)?;
+    let metadata: Value =
+        serde_json::from_slice(&fs::read(&export)?).context("Failed to parse exported metadata")?;
+    Ok(count_inodes(&metadata))
+}
+
+/// 运行 dwarfsck 并返回 stdout。
+fn dwarfsck(args: &[&str], image: &Path) -> Result<Vec<u8>> {
+    let output = Command::new(temp_dir().join("dwarfsck.exe"))
+        .args(args)
+        .arg(image)
+        .stderr(Stdio::inherit())
+        .output()?;
+    ensure!(
+        output.status.success(),
+        "dwarfsck exited with {}",
+        output.status
+    );
+    Ok(output.stdout)
+}
+
+/// 用 dwarfsck 读取归档的概要信息。
+pub fn archive_info(image: &Path) -> Result<ArchiveInfo> {
+    unpack_all()?;
+    ensure!(
+        image.is_file(),
+        "Input path is not a file: {}",
+        image.display()
+    );
+    let mut info = ArchiveInfo {
+        path: image.to_path_buf(),
+        compressed_size: fs::metadata(image)?.len(),
+        ..ArchiveInfo::default()
+    };
+
+    let fsinfo: Value = serde_json::from_slice(&dwarfsck(&["--json", "-d", "3"], image)?)
+        .context("Failed to parse dwarfsck output")?;
+    parse_fsinfo(&fsinfo, &mut info);
+
+    let counts = match Image::open(image) {
+        Ok(opened) => {
+            // 旧版 dwarfsck 的 JSON 中可能没有 section 信息
+            if info.section_compression.is_empty() {
+                for section in &opened.sections {
+                    info.section_compression
+                        .entry(section.kind.name())
+                        .or_default()
+                        .insert(section.compression.name());
+                }
+            }
+            Some(tally_modes(
+                opened.metadata.inodes.iter().map(|inode| inode.mode),
+            ))
+        },
+        Err(_) => count_inodes_with_dwarfsck(image)?,
+    };
+    if let Some((files, directories, symlinks)) = counts {
+        info.files = Some(files);
+        info.directories = Some(directories);
+        info.symlinks = Some(symlinks);
+    }
+    Ok(info)
+}
+
+/// 以两列表格的形式输出。
+pub fn print_info(info: &ArchiveInfo) {
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let count = |value: Option<u64>| or_unknown(value.map(|n| n.to_string()));
+    let ratio = info.original_size.filter(|&size| size > 0).map(|original| {
+        #[allow(clippy::cast_precision_loss)]
+        let percent = info.compressed_size as f64 / original as f64 * 100.0;
+        format!("{percent:.1}%")
+    });
+    let mut rows = vec![
+        ("Archive", info.path.display().to_string()),
+        ("Image version", or_unknown(info.image_version.clone())),
+        ("Created by", or_unknown(info.created_by.clone())),
+        ("Block size", or_unknown(info.block_size.map(format_size))),
+        ("Blocks", count(info.block_count)),
+        (
+            "Original size",
+            or_unknown(info.original_size.map(format_size)),
+        ),
+        ("Compressed size", format_size(info.compressed_size)),
+        ("Ratio", or_unknown(ratio)),
+        ("Files", count(info.files)),
+        ("Directories", count(info.directories)),
+        ("Symlinks", count(info.symlinks)),
+    ];
+    for (section, algorithms) in &info.section_compression {
+        let algorithms = algorithms.iter().cloned().collect::<Vec<_>>().join(", ");
+        rows.push(("Compression", format!("{section}: {algorithms}")));
+    }
+    for arguments in &info.creation_options {
+        rows.push(("Created with", arguments.join(" ")));
+    }
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    for (label, value) in rows {
+        println!("{label:<width$}  {value}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn fsinfo_fields_are_extracted() {
+        let fsinfo = json!({
+            "version": {"major": 2, "minor": 5, "header": 2},
+            "created_by": "libdwarfs v0.12.4",
+            "block_size": 16_777_216,
+            "block_count": 3,
+            "original_filesystem_size": 1000,
+            "sections": [
+                {"type": "BLOCK", "compression": "ZSTD"},
+                {"type": "BLOCK", "compression": "LZMA"},
+                {"type": "METADATA_V2", "compression": "ZSTD"},
+            ],
+            "history": [{"arguments": ["mkdwarfs", "-l", "9"]}],
+        });
+        let mut info = ArchiveInfo::default();
+        parse_fsinfo(&fsinfo, &mut info);
+        assert!(info.image_version.as_deref() == Some("2.5"));
+        assert!(info.block_count == Some(3) && info.original_size == Some(1000));
+        assert!(info.section_compression["BLOCK"].len() == 2);
+        assert!(info.creation_options == [["mkdwarfs", "-l", "9"]]);
+    }
+
+    #[test]
+    fn inodes_are_counted_by_mode() {
+        let metadata = json!({
+            "modes": [0o040_755, 0o100_644, 0o120_777],
+            "inodes": [
+                {"mode_index": 0}, {"mode_index": 0},
+                {"mode_index": 2},
+                {"mode_index": 1}, {"mode_index": 1}, {"mode_index": 1},
+            ],
+        });
+        assert!(count_inodes(&metadata) == Some((3, 2, 1)));
+        assert!(count_inodes(&json!({})).is_none());
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, ensure};
+use clap::Args;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Serialize, Serializer};
+use windows::Win32::{Foundation::SYSTEMTIME, System::Time::TzSpecificLocalTimeToSystemTime};
+
+use crate::{
+    compress::{temp_dir, unpack_all},
+    image::{Image, InodeKind, Metadata, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG},
+};
+
+/// 归档中的一个条目，`--json` 时原样序列化输出。
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// 相对于归档根目录、以 `/` 分隔的路径
+    pub path: String,
+    /// `ls -l` 形式的权限，例如 `drwxr-xr-x`
+    pub mode: String,
+    pub size: u64,
+    /// Unix 时间戳（秒），输出时格式化为 UTC 时间
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub mtime: u64,
+    /// `mtime` 的精度（秒）。dwarfsck 只输出到分钟，镜像也可能以更低的精度记录时间
+    #[serde(skip)]
+    pub mtime_resolution: u64,
+    /// 符号链接的目标
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.mode.starts_with('d')
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn serialize_timestamp<S: Serializer>(secs: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_timestamp(*secs))
+}
+
+/// 选择要列出的条目。
+#[derive(Args, Debug, Clone, Default)]
+pub struct ListFilter {
+    /// Only list entries below this folder in the archive
+    #[arg(long, value_name = "PATH")]
+    pub prefix: Option<String>,
+    /// Only descend this many levels below the prefix (1 lists its direct children)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+    /// Only list entries matching this glob. Without a `/` it is matched against the file name,
+    /// otherwise against the path relative to the archive root.
+    #[arg(long, value_name = "GLOB")]
+    pub glob: Option<String>,
+}
+
+/// 把路径规范化为不带首尾 `/` 的 `/` 分隔形式。
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let path = path.trim_start_matches("./");
+    path.trim_matches('/').to_string()
+}
+
+/// 取出行首的 `n` 个以空白分隔的字段，返回这些字段和剩余部分。
+fn split_fields(line: &str, n: usize) -> Option<(Vec<&str>, &str)> {
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Some((fields, rest))
+}
+
+/// 把 dwarfsck 输出的本地时间 `YYYY-MM-DD HH:MM[:SS]` 转换为 Unix 时间戳，返回时间戳和精度。
+fn parse_local_time(date: &str, time: &str) -> Option<(u64, u64)> {
+    let date = date
+        .split('-')
+        .map(|field| field.parse().ok())
+        .collect::<Option<Vec<u16>>>()?;
+    let time = time
+        .split(':')
+        .map(|field| field.parse().ok())
+        .collect::<Option<Vec<u16>>>()?;
+    let (&[year, month, day], &[hour, minute, ref second @ ..]) = (&date[..], &time[..]) else {
+        return None;
+    };
+    let resolution = match second {
+        [] => 60,
+        [_] => 1,
+        _ => return None,
+    };
+    let local = SYSTEMTIME {
+        wYear: year,
+        wMonth: month,
+        wDay: day,
+        wHour: hour,
+        wMinute: minute,
+        wSecond: second.first().copied().unwrap_or(0),
+        ..SYSTEMTIME::default()
+    };
+    let mut utc = SYSTEMTIME::default();
+    unsafe { TzSpecificLocalTimeToSystemTime(None, &raw const local, &raw mut utc) }.ok()?;
+    let days = days_from_civil(utc.wYear.into(), utc.wMonth.into(), utc.wDay.into())?;
+    let secs = u64::from(utc.wHour) * 3600 + u64::from(utc.wMinute) * 60 + u64::from(utc.wSecond);
+    Some((days * 86_400 + secs, resolution))
+}
+
+/// 解析 `dwarfsck --list --verbose` 的一行，形如
+/// `-rw-r--r-- 1000/100 1234 2024-05-01 12:34 dir/file.txt`，时间为本地时间。
+fn parse_line(line: &str) -> Result<Option<Entry>> {
+    let invalid = || format!("Unexpected dwarfsck output: `{line}`");
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let (fields, rest) = split_fields(line, 4).with_context(invalid)?;
+    let (mode, size, date) = (fields[0], fields[2], fields[3]);
+    let size = size.parse().with_context(invalid)?;
+    let (time, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let (mtime, mtime_resolution) = parse_local_time(date, time).with_context(invalid)?;
+    let rest = rest.trim_start();
+    let (path, target) = match rest.split_once(" -> ") {
+        Some((path, target)) if mode.starts_with('l') => (path, Some(target.to_string())),
+        _ => (rest, None),
+    };
+    let path = normalize(path);
+    if path.is_empty() {
+        // 根目录本身
+        return Ok(None);
+    }
+    Ok(Some(Entry {
+        path,
+        mode: mode.to_string(),
+        size,
+        mtime,
+        mtime_resolution,
+        target,
+    }))
+}
+
+pub fn parse_listing(output: &str) -> Result<Vec<Entry>> {
+    output
+        .lines()
+        .filter_map(|line| parse_line(line).transpose())
+        .collect()
+}
+
+impl ListFilter {
+    fn matcher(&self) -> Result<Option<(GlobMatcher, bool)>> {
+        let Some(glob) = &self.glob else {
+            return Ok(None);
+        };
+        let matcher = GlobBuilder::new(glob)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid glob `{glob}`"))?
+            .compile_matcher();
+        Ok(Some((matcher, glob.contains('/'))))
+    }
+
+    /// 按前缀、深度和 glob 过滤条目，保持原有顺序。
+    pub fn apply(&self, entries: Vec<Entry>) -> Result<Vec<Entry>> {
+        let prefix = self.prefix.as_deref().map(normalize).unwrap_or_default();
+        let matcher = self.matcher()?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| {
+                let relative = if prefix.is_empty() {
+                    entry.path.as_str()
+                } else if entry.path == prefix {
+                    // 前缀本身是文件时列出它，是文件夹时只列出其内容
+                    return !entry.is_dir() && Self::glob_matches(matcher.as_ref(), entry);
+                } else if let Some(relative) = entry
+                    .path
+                    .strip_prefix(&prefix)
+                    .and_then(|rest| rest.strip_prefix('/'))
+                {
+                    relative
+                } else {
+                    return false;
+                };
+                let depth = relative.split('/').count();
+                self.depth
+                    .is_none_or(|max| depth <= usize::try_from(max).unwrap_or(usize::MAX))
+                    && Self::glob_matches(matcher.as_ref(), entry)
+            })
+            .collect())
+    }
+
+    fn glob_matches(matcher: Option<&(GlobMatcher, bool)>, entry: &Entry) -> bool {
+        matcher.is_none_or(|(matcher, full_path)| {
+            if *full_path {
+                matcher.is_match(&entry.path)
+            } else {
+                matcher.is_match(entry.path.rsplit('/').next().unwrap_or_default())
+            }
+        })
+    }
+}
+
+/// `ls -l` 形式的权限字符串。
+fn mode_string(mode: u32) -> String {
+    let kind = match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        S_IFREG => '-',
+        0o020_000 => 'c',
+        0o060_000 => 'b',
+        0o010_000 => 'p',
+        0o140_000 => 's',
+        _ => '?',
+    };
+    std::iter::once(kind)
+        .chain((0..9).rev().map(|bit| {
+            if mode & (1 << bit) == 0 {
+                '-'
+            } else {
+                ['x', 'w', 'r'][bit % 3]
+            }
+        }))
+        .collect()
+}
+
+/// 公历日期距 1970-01-01 的天数，早于 1970 年时返回 `None`。
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = year - u64::from(month <= 2);
+    let (era, year_of_era) = (year / 400, year % 400);
+    let month = if month > 2 {
+        month - 3
+    } else {
+        month + 9
+    };
+    let day_of_year = (153 * month + 2) / 5 + day.checked_sub(1)?;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+/// 把 Unix 时间戳格式化为 `YYYY-MM-DD HH:MM:SS`（UTC）。
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // 从 0000-03-01 起算的公历换算，见 https://howardhinnant.github.io/date_algorithms.html
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 {
+        month + 3
+    } else {
+        month - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// 从解析好的元数据生成条目列表。
+fn metadata_listing(metadata: &Metadata) -> Vec<Entry> {
+    let mut entries = Vec::with_capacity(metadata.inodes.len());
+    metadata.walk(|path, inode| {
+        entries.push(Entry {
+            path: path.to_string(),
+            mode: mode_string(inode.mode),
+            size: inode.size(),
+            mtime: inode.mtime,
+            mtime_resolution: metadata.time_resolution,
+            target: match &inode.kind {
+                InodeKind::Symlink(target) => Some(target.clone()),
+                _ => None,
+            },
+        });
+    });
+    entries
+}
+
+/// 列出归档内容，不需要挂载。优先直接读取镜像，元数据格式不支持时改用 dwarfsck。
+pub fn list_archive(image: &Path, filter: &ListFilter) -> Result<Vec<Entry>> {
+    ensure!(
+        image.is_file(),
+        "Input path is not a file: {}",
+        image.display()
+    );
+    let entries = match Image::open(image) {
+        Ok(opened) => metadata_listing(&opened.metadata),
+        Err(error) => {
+            eprintln!("Falling back to dwarfsck: {error:#}");
+            dwarfsck_listing(image)?
+        },
+    };
+    filter.apply(entries)
+}
+
+fn dwarfsck_listing(image: &Path) -> Result<Vec<Entry>> {
+    unpack_all()?;
+    let output = Command::new(temp_dir().join("dwarfsck.exe"))
+        .arg("--list")
+        .arg("--verbose")
+        .arg(image)
+        .stderr(Stdio::inherit())
+        .output()?;
+    ensure!(
+        output.status.success(),
+        "dwarfsck exited with {}",
+        output.status
+    );
+    parse_listing(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// 以 `ls -l` 的形式输出，大小为字节数以便脚本处理。
+pub fn print_listing(entries: &[Entry]) {
+    let width = entries
+        .iter()
+        .map(|entry| entry.size.to_string().len())
+        .max()
+        .unwrap_or(0);
+    for entry in entries {
+        match &entry.target {
+            Some(target) => println!(
+                "{} {:>width$} {} {} -> {target}",
+                entry.mode,
+                entry.size,
+                format_timestamp(entry.mtime),
+                entry.path
+            ),
+            None => println!(
+                "{} {:>width$} {} {}",
+                entry.mode,
+                entry.size,
+                format_timestamp(entry.mtime),
+                entry.path
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = "\
+drwxr-xr-x 1000/100        0 2024-05-01 12:00:00 
+drwxr-xr-x 1000/100        0 2024-05-01 12:00:00 docs
+-rw-r--r-- 1000/100     1234 2024-05-01 12:34 docs/read me.txt
+drwxr-xr-x 1000/100        0 2024-05-01 12:00:00 docs/old
+-rw-r--r-- 1000/100       10 2024-05-01 12:00:00 docs/old/a.md
+lrwxrwxrwx 1000/100        6 2024-05-01 12:00:00 latest -> docs/old
+";
+
+    fn paths(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn verbose_listing_is_parsed() {
+        let entries = parse_listing(LISTING).unwrap();
+        assert!(
+            paths(&entries)
+                == [
+                    "docs",
+                    "docs/read me.txt",
+                    "docs/old",
+                    "docs/old/a.md",
+                    "latest"
+                ]
+        );
+        // 本地时间对应的时间戳取决于时区，只比较两个条目的间隔
+        assert!(entries[1].size == 1234 && entries[1].mtime_resolution == 60);
+        assert!(entries[1].mtime - entries[0].mtime == 34 * 60);
+        assert!(entries[4].target.as_deref() == Some("docs/old"));
+        assert!(parse_listing("garbage line here").is_err());
+    }
+
+    #[test]
+    fn filter_by_prefix_depth_and_glob() {
+        let entries = parse_listing(LISTING).unwrap();
+        let filter = ListFilter {
+            prefix: Some("/docs/".to_string()),
+            depth: Some(1),
+            glob: None,
+        };
+        assert!(paths(&filter.apply(entries.clone()).unwrap()) == ["docs/read me.txt", "docs/old"]);
+        let filter = ListFilter {
+            glob: Some("*.md".to_string()),
+            ..ListFilter::default()
+        };
+        assert!(paths(&filter.apply(entries.clone()).unwrap()) == ["docs/old/a.md"]);
+        let filter = ListFilter {
+            glob: Some("docs/*".to_string()),
+            ..ListFilter::default()
+        };
+        assert!(paths(&filter.apply(entries).unwrap()) == ["docs/read me.txt", "docs/old"]);
+    }
+
+    #[test]
+    fn native_fields_are_formatted() {
+        assert!(mode_string(0o040_755) == "drwxr-xr-x");
+        assert!(mode_string(0o100_640) == "-rw-r-----");
+        assert!(format_timestamp(0) == "1970-01-01 00:00:00");
+        assert!(format_timestamp(1_714_567_496) == "2024-05-01 12:44:56");
+        assert!(days_from_civil(2024, 5, 1) == Some(1_714_567_496 / 86_400));
+        assert!(days_from_civil(1970, 1, 1) == Some(0) && days_from_civil(1969, 12, 31).is_none());
+    }
+}
@@ -1,42 +1,94 @@
 mod compress;
+mod config;
+mod conflict;
+mod diff;
 mod edit_reg;
+mod export;
 mod file_dialog;
+mod image;
+mod import;
+mod info;
+mod list;
 mod mount;
+mod mounts;
+mod options;
+mod progress;
+mod staging;
+mod verify;
 use std::{
     io::Read,
     path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 
-use crate::compress::{compress_path_to_dwarfs, decompress_dwarfs_to_folder};
+use crate::{
+    compress::{
+        CompressBehavior, DecompressBehavior, DeleteMode, compress_paths_to_dwarfs,
+        decompress_dwarfs_to_folder, default_archive_path, preview_compression, recompress_dwarfs,
+    },
+    config::Config,
+    conflict::{FileConflict, OnConflict},
+    diff::diff,
+    export::{ArchiveFormat, export_dwarfs},
+    import::{default_import_path, import_archive_to_dwarfs},
+    info::{archive_info, print_info},
+    list::{ListFilter, list_archive, print_listing},
+    options::{CompressOptions, ExtractFilter, MountOptions, RecompressSection},
+    progress::{ProgressMode, status},
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// How to report progress of mkdwarfs/dwarfsextract
+    #[arg(long, global = true, value_enum, default_value_t)]
+    progress: ProgressMode,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Uninstall context menu entries
     Uninstall,
-    /// Compress file or folder
+    /// Compress files or folders
     #[command(visible_alias = "c")]
     Compress {
-        /// Input file or folder path
-        input: PathBuf,
-        /// Output file path (optional). If not provided, it will be generated automatically.
+        /// Input file or folder paths. Multiple inputs are bundled into one archive.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Output file path (optional). If not provided, it will be generated automatically: the
+        /// input path plus `.dwarfs`, or for multiple inputs an archive named after their parent
+        /// folder.
         #[arg(short, long)]
         output: Option<PathBuf>,
-        /// Compression level (0-9, default 7)
-        #[arg(short, long, value_parser = clap::value_parser!(i32).range(0..=9))]
-        compression_level: Option<i32>,
+        /// Named compression preset (store, fast, balanced, max or one from the config file).
+        /// Explicit options below override the preset.
+        #[arg(short, long)]
+        preset: Option<String>,
+        #[command(flatten)]
+        options: Box<CompressOptions>,
         /// Interactively select where the file/folder will be compressed to
         #[arg(short, long)]
         interactive: bool,
+        /// What to do when the output archive already exists (default: from the config file, or
+        /// `rename`)
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+        /// Check the new archive's integrity and compare every file, folder and symlink with the
+        /// source (default: from the config file). Use `--verify=false` to skip.
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        verify: Option<bool>,
+        /// Remove the inputs after the archive has been created and verified. They go to the
+        /// Recycle Bin unless `--delete-source=permanent` is given.
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "recycle")]
+        delete_source: Option<DeleteMode>,
+        /// Only list the files that would be included, without creating an archive
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Decompress file or folder
     #[command(visible_alias = "d")]
@@ -50,6 +102,136 @@ enum Commands {
         /// Interactively select where the decompressed file will be saved
         #[arg(short, long)]
         interactive: bool,
+        /// What to do when the output folder already exists (default: from the config file, or
+        /// `rename`)
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+        #[command(flatten)]
+        filter: ExtractFilter,
+        /// If the archive holds a single file or folder, extract it next to the archive instead
+        /// of into a folder of the same name. With `--output`, it is extracted to exactly that
+        /// path.
+        #[arg(long)]
+        smart: bool,
+        /// If the output folder already exists, extract into it and handle each existing file with
+        /// this policy, then list what was replaced or skipped. `--on-conflict overwrite` implies
+        /// `--merge overwrite`.
+        #[arg(long, value_enum)]
+        merge: Option<FileConflict>,
+    },
+    /// Rewrite a dwarfs file with different compression settings, without extracting it
+    Recompress {
+        /// Input file path
+        input: PathBuf,
+        /// Output file path (optional). If not provided, the input file is replaced.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Named compression preset (store, fast, balanced, max or one from the config file).
+        /// Explicit options below override the preset.
+        #[arg(short, long)]
+        preset: Option<String>,
+        /// Which parts of the archive to recompress
+        #[arg(long, value_enum, default_value_t)]
+        section: RecompressSection,
+        /// Compression options. Block size, window, order and filters are fixed when an archive
+        /// is created and are ignored here.
+        #[command(flatten)]
+        options: Box<CompressOptions>,
+        /// What to do when the output archive already exists (default: from the config file, or
+        /// `rename`)
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+    },
+    /// Show how a dwarfs file was built and what it contains
+    Info {
+        /// Input file path
+        input: PathBuf,
+        /// Print the information as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the contents of a dwarfs file without mounting it
+    #[command(visible_alias = "ls")]
+    List {
+        /// Input file path
+        input: PathBuf,
+        #[command(flatten)]
+        filter: ListFilter,
+        /// Print the entries as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare two dwarfs files, or a dwarfs file and a folder. Exits with 0 if they are the
+    /// same, 1 if they differ and 2 on errors.
+    Diff {
+        /// The old dwarfs file or folder
+        old: PathBuf,
+        /// The new dwarfs file or folder
+        new: PathBuf,
+        /// Also compare file contents by SHA-256, not only size and modification time
+        #[arg(long)]
+        hash: bool,
+        /// Don't report files whose modification time is the only difference
+        #[arg(long)]
+        ignore_mtime: bool,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Convert a dwarfs file to a tar, zip or cpio archive
+    #[command(visible_alias = "export")]
+    Convert {
+        /// Input file path
+        input: PathBuf,
+        /// Output archive path, or `-` to write to stdout (optional). If not provided, it will be
+        /// the input path with the format's extension instead of `.dwarfs`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Archive format (default: detected from the output extension, or `tar`)
+        #[arg(short, long, value_enum)]
+        format: Option<ArchiveFormat>,
+        /// What to do when the output archive already exists (default: from the config file, or
+        /// `rename`)
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+        #[command(flatten)]
+        filter: ExtractFilter,
+    },
+    /// Convert a zip or tar archive to a dwarfs file
+    Import {
+        /// Input .zip, .tar or compressed .tar file path
+        input: PathBuf,
+        /// Output file path (optional). If not provided, it will be the input path with the
+        /// archive extension replaced by `.dwarfs`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Named compression preset (store, fast, balanced, max or one from the config file).
+        /// Explicit options below override the preset.
+        #[arg(short, long)]
+        preset: Option<String>,
+        #[command(flatten)]
+        options: Box<CompressOptions>,
+        /// What to do when the output archive already exists (default: from the config file, or
+        /// `rename`)
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+        /// Check the new archive's integrity and compare every file, folder and symlink with the
+        /// extracted content (default: from the config file). Use `--verify=false` to skip.
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        verify: Option<bool>,
+    },
+    /// Show dwarfs files mounted by this tool
+    Mounts {
+        /// Only show mounts of this dwarfs file
+        archive: Option<PathBuf>,
+        /// Print the mounts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Unmount a dwarfs file mounted by this tool
+    Unmount {
+        /// Drive letter, mount folder or dwarfs file path of the mount
+        target: String,
     },
     /// Mount dwarfs file as drive or folder
     #[command(visible_alias = "m")]
@@ -59,9 +241,38 @@ enum Commands {
         /// Output drive letter (ends with ':') or folder path (optional). If not provided, it will
         /// be a usable drive letter.
         dest: Option<String>,
+        /// Keep dwarfs running in the background and return once the mount is reachable, instead
+        /// of waiting until it is unmounted
+        #[arg(short, long)]
+        detach: bool,
+        /// Mount into a folder next to the dwarfs file named after it without `.dwarfs`, which is
+        /// created for the mount and removed again when it is unmounted
+        #[arg(long, conflicts_with = "dest")]
+        here: bool,
+        #[command(flatten)]
+        options: MountOptions,
     },
 }
 
+impl Cli {
+    /// 是否把数据写到 stdout，此时不能输出按键提示。
+    fn writes_to_stdout(&self) -> bool {
+        match &self.command {
+            Some(Commands::Convert {
+                output: Some(output),
+                ..
+            }) => output.as_os_str() == "-",
+            Some(
+                Commands::Info { json, .. }
+                | Commands::List { json, .. }
+                | Commands::Diff { json, .. }
+                | Commands::Mounts { json, .. },
+            ) => *json,
+            _ => false,
+        }
+    }
+}
+
 struct PauseGuard;
 
 impl Drop for PauseGuard {
@@ -95,25 +306,55 @@ impl PathExt for Path {
     }
 }
 
-fn main() -> Result<()> {
-    let _guard = PauseGuard;
+/// `diff` 出错时的退出码。与 diff/cmp 一致，0 和 1 分别表示相同和不同。
+const DIFF_ERROR: u8 = 2;
+
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
-    run(cli)
+    // JSON 模式和输出到 stdout 时供脚本使用，不能往 stdout 混入提示，也不应等待按键
+    let guard =
+        (cli.progress != ProgressMode::Json && !cli.writes_to_stdout()).then_some(PauseGuard);
+    // 后台挂载成功后窗口直接关闭；失败时仍然等待按键，以便看到错误
+    let detached_mount = matches!(cli.command, Some(Commands::Mount { detach: true, .. }));
+    let is_diff = matches!(cli.command, Some(Commands::Diff { .. }));
+    let result = run(cli);
+    if detached_mount && result.is_ok() {
+        std::mem::forget(guard);
+    }
+    match result {
+        Err(e) if is_diff => {
+            eprintln!("Error: {e:?}");
+            Ok(ExitCode::from(DIFF_ERROR))
+        },
+        result => result,
+    }
 }
 
-fn run(cli: Cli) -> Result<()> {
+fn run(cli: Cli) -> Result<ExitCode> {
+    progress::set_mode(cli.progress);
     match cli.command {
         Some(Commands::Uninstall) => {
             edit_reg::remove_context_menu_entries()?;
         },
         Some(Commands::Compress {
-            input,
+            inputs,
             mut output,
-            compression_level,
+            preset,
+            options,
             interactive,
+            on_conflict,
+            verify,
+            delete_source,
+            dry_run,
         }) => {
+            let config = Config::load()?;
+            let options = options.or(&config.preset(preset.as_deref())?);
+            if dry_run {
+                preview_compression(&inputs, &options)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let default_output = default_archive_path(&inputs)?;
             if interactive {
-                let default_output = input.add_ext();
                 let Some(selected) = file_dialog::save_file_dialog(
                     &["dwarfs"],
                     default_output
@@ -125,22 +366,32 @@ fn run(cli: Cli) -> Result<()> {
                         .to_string_lossy()
                         .as_ref(),
                 ) else {
-                    println!("Operation cancelled by user");
-                    return Ok(());
+                    status("Operation cancelled by user");
+                    return Ok(ExitCode::SUCCESS);
                 };
                 output = Some(selected);
             }
-            compress_path_to_dwarfs(
-                &input,
-                output.unwrap_or_else(|| input.add_ext()),
-                compression_level,
+            compress_paths_to_dwarfs(
+                &inputs,
+                output.unwrap_or(default_output),
+                &options,
+                CompressBehavior {
+                    on_conflict: on_conflict.unwrap_or(config.on_conflict),
+                    verify: verify.unwrap_or(config.verify),
+                    delete_source,
+                },
             )?;
         },
         Some(Commands::Decompress {
             input,
             mut output,
             interactive,
+            on_conflict,
+            filter,
+            smart,
+            merge,
         }) => {
+            let config = Config::load()?;
             if interactive {
                 let default_output = input.rm_ext();
                 let Some(selected) = file_dialog::save_file_dialog(
@@ -154,23 +405,173 @@ fn run(cli: Cli) -> Result<()> {
                         .to_string_lossy()
                         .as_ref(),
                 ) else {
-                    println!("Operation cancelled by user");
-                    return Ok(());
+                    status("Operation cancelled by user");
+                    return Ok(ExitCode::SUCCESS);
                 };
                 output = Some(selected);
             }
-            decompress_dwarfs_to_folder(&input, output.unwrap_or_else(|| input.rm_ext()))?;
+            decompress_dwarfs_to_folder(&input, output.as_deref(), &filter, DecompressBehavior {
+                on_conflict: on_conflict.unwrap_or(config.on_conflict),
+                smart,
+                merge,
+            })?;
+        },
+        Some(Commands::Recompress {
+            input,
+            output,
+            preset,
+            section,
+            options,
+            on_conflict,
+        }) => {
+            let config = Config::load()?;
+            let options = options.or(&config.preset(preset.as_deref())?);
+            recompress_dwarfs(
+                &input,
+                output.as_deref(),
+                &options,
+                section,
+                on_conflict.unwrap_or(config.on_conflict),
+            )?;
+        },
+        Some(Commands::Info { input, json }) => {
+            let info = archive_info(&input)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                print_info(&info);
+            }
+        },
+        Some(Commands::List {
+            input,
+            filter,
+            json,
+        }) => {
+            let entries = list_archive(&input, &filter)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                print_listing(&entries);
+            }
+        },
+        Some(Commands::Diff {
+            old,
+            new,
+            hash,
+            ignore_mtime,
+            json,
+        }) => {
+            let report = diff(&old, &new, hash, ignore_mtime)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report.print();
+            }
+            if !report.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        Some(Commands::Convert {
+            input,
+            output,
+            format,
+            on_conflict,
+            filter,
+        }) => {
+            let config = Config::load()?;
+            let format = match (format, &output) {
+                (Some(format), _) => format,
+                (None, Some(output)) if output.as_os_str() != "-" => {
+                    ArchiveFormat::from_path(output).with_context(|| {
+                        format!(
+                            "Can't detect the archive format of {}, use --format",
+                            output.display()
+                        )
+                    })?
+                },
+                (None, _) => ArchiveFormat::Tar,
+            };
+            let output = match output {
+                Some(output) if output.as_os_str() == "-" => None,
+                Some(output) => Some(output),
+                None => {
+                    let mut name = input.rm_ext().into_os_string();
+                    name.push(".");
+                    name.push(format.extension());
+                    Some(PathBuf::from(name))
+                },
+            };
+            export_dwarfs(
+                &input,
+                output.as_deref(),
+                format,
+                on_conflict.unwrap_or(config.on_conflict),
+                &filter,
+            )?;
+        },
+        Some(Commands::Import {
+            input,
+            output,
+            preset,
+            options,
+            on_conflict,
+            verify,
+        }) => {
+            let config = Config::load()?;
+            let options = options.or(&config.preset(preset.as_deref())?);
+            import_archive_to_dwarfs(
+                &input,
+                output.unwrap_or_else(|| default_import_path(&input)),
+                &options,
+                CompressBehavior {
+                    on_conflict: on_conflict.unwrap_or(config.on_conflict),
+                    verify: verify.unwrap_or(config.verify),
+                    delete_source: None,
+                },
+            )?;
         },
         None => {
             // When executed without arguments, add context menu entries
-            edit_reg::add_context_menu_entries()?;
+            // 配置文件写错时仍要能安装菜单，只给出警告并使用默认设置
+            let config = Config::load().unwrap_or_else(|e| {
+                eprintln!("Warning: {e:#}, using the default settings");
+                Config::default()
+            });
+            edit_reg::add_context_menu_entries(&config.menu)?;
+        },
+        Some(Commands::Mount {
+            input,
+            dest,
+            detach,
+            here,
+            options,
+        }) => {
+            let config = Config::load()?;
+            mount::mount_dwarfs(&input, dest, &options.or(&config.mount), detach, here)?;
+        },
+        Some(Commands::Mounts { archive, json }) => {
+            let mut statuses = mounts::mount_statuses()?;
+            if let Some(archive) = &archive {
+                let archive = archive.to_string_lossy();
+                statuses.retain(|status| status.record.matches(&archive));
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&statuses)?);
+            } else if statuses.is_empty() {
+                match archive {
+                    Some(archive) => println!("{} is not mounted", archive.display()),
+                    None => println!("No dwarfs files are mounted"),
+                }
+            } else {
+                mounts::print_mounts(&statuses);
+            }
         },
-        Some(Commands::Mount { input, dest }) => {
-            mount::mount_dwarfs(input, dest)?;
+        Some(Commands::Unmount { target }) => {
+            mounts::unmount(&target)?;
         },
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 #[cfg(test)]
@@ -1,9 +1,20 @@
-use std::process::Command;
+use std::{
+    fs, io,
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail, ensure};
 use windows::Win32::Storage::FileSystem::GetLogicalDrives;
 
-use crate::compress::{temp_dir, unpack_all};
+use crate::{
+    PathExt,
+    compress::{temp_dir, unpack_all},
+    mounts::{self, MountRecord, is_drive_letter, normalize_mountpoint},
+    options::MountOptions,
+};
 
 /// 从盘符位掩码中选出 Z→A 方向首个未使用的盘符。
 fn first_unused_from_mask(drives_mask: u32) -> Option<char> {
@@ -23,25 +34,217 @@ pub fn get_first_unused_drive_letter() -> Option<String> {
     first_unused_from_mask(drives_mask).map(|c| format!("{c}:"))
 }
 
+/// 后台挂载时等待挂载点可访问的最长时间。
+const MOUNT_TIMEOUT: Duration = Duration::from_secs(30);
+/// 后台挂载时检查挂载点的间隔。
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// 不附加到当前控制台，关闭控制台窗口不会结束 dwarfs。
+#[cfg(windows)]
+const DETACHED_PROCESS: u32 = 0x0000_0008;
+
+/// 输出 dwarfs 的错误信息，缺少 WinFsp 时给出安装提示。
+fn mount_error(stderr: &str, status: ExitStatus) -> anyhow::Error {
+    eprintln!("Failed to mount dwarfs file: {stderr}");
+    if stderr.contains("FSD not found") {
+        eprintln!(
+            "Mounting dwarfs needs WinFsp to be installed. Please install it first: https://github.com/winfsp/winfsp/releases"
+        );
+    }
+    anyhow!("dwarfs exited with {status}")
+}
+
+/// 挂载点是否已经可以访问。盘符要加上 `\` 才表示其根目录。
+fn is_reachable(mountpoint: &str) -> bool {
+    if is_drive_letter(mountpoint) {
+        fs::read_dir(format!("{mountpoint}\\")).is_ok()
+    } else {
+        fs::read_dir(mountpoint).is_ok()
+    }
+}
+
+/// 挂载点在挂载前是否已被占用。后台挂载时，只有事先不存在的挂载点变得可以访问，才说明挂载已经
+/// 完成；WinFsp 也会自行创建挂载文件夹。
+fn is_in_use(mountpoint: &str) -> bool {
+    if is_drive_letter(mountpoint) {
+        is_reachable(mountpoint)
+    } else {
+        fs::symlink_metadata(mountpoint).is_ok()
+    }
+}
+
+/// 挂载到归档旁边的同名文件夹前检查该文件夹。
+///
+/// WinFsp 要求挂载文件夹事先不存在，会在挂载时自行创建，因此已存在的空文件夹会先被删除，非空的
+/// 文件夹则拒绝挂载。返回是否删除了已存在的文件夹，挂载失败时需要恢复它。
+fn prepare_mount_folder(folder: &Path) -> Result<bool> {
+    let existed = match fs::read_dir(folder) {
+        Ok(mut entries) => {
+            ensure!(
+                entries.next().is_none(),
+                "Mount folder is not empty: {}",
+                folder.display()
+            );
+            fs::remove_dir(folder)?;
+            true
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => {
+            return Err(e).with_context(|| format!("Can't use mount folder {}", folder.display()));
+        },
+    };
+    if let Some(parent) = folder.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(existed)
+}
+
+/// 挂载失败后重建事先删除的空文件夹。先等 WinFsp 移除挂载时创建的文件夹，否则它随后会被删掉。
+fn restore_mount_folder(record: &MountRecord) {
+    record.remove_mount_folder();
+    match fs::create_dir(&record.mountpoint) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(e) => eprintln!("Failed to restore mount folder {}: {e}", record.mountpoint),
+    }
+}
+
 /// 挂载 dwarfs 文件为盘符或文件夹。
-pub fn mount_dwarfs(input: std::path::PathBuf, dest: Option<String>) -> Result<()> {
+///
+/// `beside` 时挂载到归档旁边去掉 `.dwarfs` 后缀的文件夹，卸载后删除该文件夹。
+///
+/// 默认在前台运行直到被卸载或 dwarfs 退出；`detach` 时让 dwarfs 在后台运行，挂载点可以访问后
+/// 立即返回。挂载会记录在挂载记录文件中，供 `unmount` 和 `mounts` 使用。
+pub fn mount_dwarfs(
+    input: &Path,
+    dest: Option<String>,
+    options: &MountOptions,
+    detach: bool,
+    beside: bool,
+) -> Result<()> {
+    options.validate()?;
     unpack_all()?;
-    let dest = dest
-        .or_else(get_first_unused_drive_letter)
-        .context("No available drive letter")?;
+    let mut folder_existed = false;
+    let dest = if beside {
+        ensure!(
+            dest.is_none(),
+            "A destination can't be given when mounting beside the archive"
+        );
+        let folder = input.rm_ext();
+        ensure!(
+            folder != input,
+            "Can't mount {} beside itself: it has no .dwarfs extension",
+            input.display()
+        );
+        folder_existed = prepare_mount_folder(&folder)?;
+        folder.to_string_lossy().into_owned()
+    } else {
+        dest.or_else(get_first_unused_drive_letter)
+            .context("No available drive letter")?
+    };
     println!("Mount {} to `{dest}`", input.display());
-    let mut cmd = Command::new(temp_dir().join("dwarfs.exe"));
-    let output = cmd.arg(input).arg(dest).output()?;
+    let record = MountRecord::new(input, normalize_mountpoint(&dest)?, 0, beside)?;
+    let mut command = Command::new(temp_dir().join("dwarfs.exe"));
+    command
+        .arg(input)
+        .arg(&dest)
+        .args(options.to_args())
+        .stdin(Stdio::null());
+    let result = if detach {
+        mount_detached(record.clone(), command)
+    } else {
+        mount_foreground(record.clone(), command)
+    };
+    if result.is_err() && folder_existed {
+        restore_mount_folder(&record);
+    }
+    result
+}
+
+fn mount_foreground(mut record: MountRecord, mut command: Command) -> Result<()> {
+    let child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    record.pid = child.id();
+    // 记录失败不影响挂载本身，只是之后无法用 unmount 卸载
+    let registered = match mounts::register(record.clone()) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to record the mount: {e:#}");
+            false
+        },
+    };
+    let output = child.wait_with_output();
+    // 记录已被 unmount 删除说明进程是被主动结束的，unmount 会负责清理。无法读取记录时按自行退出
+    // 处理，以便报告 dwarfs 的错误
+    let unmounted = registered
+        && match mounts::unregister(record.pid) {
+            Ok(registered) => !registered,
+            Err(e) => {
+                eprintln!("Failed to remove the mount record: {e:#}");
+                false
+            },
+        };
+    let output = output?;
+    if unmounted {
+        println!("Unmounted {}", record.archive.display());
+        return Ok(());
+    }
+    record.remove_mount_folder();
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        eprintln!("Failed to mount dwarfs file: {stderr}");
-        if stderr.contains("FSD not found") {
-            eprintln!(
-                "Mounting dwarfs needs WinFsp to be installed. Please install it first: https://github.com/winfsp/winfsp/releases"
+        return Err(mount_error(
+            &String::from_utf8_lossy(&output.stderr),
+            output.status,
+        ));
+    }
+    Ok(())
+}
+
+fn mount_detached(mut record: MountRecord, mut command: Command) -> Result<()> {
+    let mountpoint = record.mountpoint.clone();
+    ensure!(
+        !is_in_use(&mountpoint),
+        "`{mountpoint}` already exists. Mount to a drive letter or folder that is not in use"
+    );
+    // dwarfs 在后台运行时没有控制台，stderr 写入临时文件，启动失败时用于报告原因
+    let log = tempfile::Builder::new()
+        .prefix("mount-")
+        .suffix(".log")
+        .tempfile_in(temp_dir())?;
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(DETACHED_PROCESS);
+    }
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(log.reopen()?)
+        .spawn()?;
+    let deadline = Instant::now() + MOUNT_TIMEOUT;
+    while !is_reachable(&mountpoint) {
+        if let Some(status) = child.try_wait()? {
+            return Err(mount_error(
+                &fs::read_to_string(log.path()).unwrap_or_default(),
+                status,
+            ));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            bail!(
+                "`{mountpoint}` was not reachable within {} seconds, mount cancelled",
+                MOUNT_TIMEOUT.as_secs()
             );
         }
-        bail!("dwarfs exited with {}", output.status);
+        thread::sleep(POLL_INTERVAL);
     }
+    record.pid = child.id();
+    if let Err(e) = mounts::register(record.clone()) {
+        eprintln!("Failed to record the mount: {e:#}");
+    }
+    println!(
+        "Mounted {} to `{mountpoint}` in the background. Run `unmount {mountpoint}` to unmount it.",
+        record.archive.display()
+    );
     Ok(())
 }
 
@@ -66,4 +269,38 @@ mod tests {
     fn returns_none_when_all_letters_used() {
         assert!(first_unused_from_mask(0x03ff_ffff).is_none());
     }
+
+    #[test]
+    fn mount_folder_is_reachable_only_when_it_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_reachable(&dir.path().to_string_lossy()));
+        assert!(!is_reachable(&dir.path().join("missing").to_string_lossy()));
+        // 已存在的文件夹即使可以访问，也不能说明挂载已经完成
+        assert!(is_in_use(&dir.path().to_string_lossy()));
+        assert!(!is_in_use(&dir.path().join("missing").to_string_lossy()));
+    }
+
+    #[test]
+    fn only_missing_or_empty_mount_folders_are_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("nested").join("archive");
+        assert!(!prepare_mount_folder(&folder).unwrap());
+        assert!(dir.path().join("nested").is_dir() && !folder.exists());
+        fs::create_dir(&folder).unwrap();
+        assert!(prepare_mount_folder(&folder).unwrap());
+        assert!(!folder.exists());
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("file"), "").unwrap();
+        assert!(prepare_mount_folder(&folder).is_err());
+    }
+
+    #[test]
+    fn removed_mount_folder_is_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("archive");
+        let mountpoint = folder.to_string_lossy().into_owned();
+        let record = MountRecord::new(&dir.path().join("archive.dwarfs"), mountpoint, 0, true);
+        restore_mount_folder(&record.unwrap());
+        assert!(folder.is_dir() && fs::read_dir(&folder).unwrap().next().is_none());
+    }
 }
@@ -0,0 +1,440 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::{self, Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{compress::temp_dir, config::data_dir};
+
+/// 挂载记录文件名，与配置文件位于同一文件夹。
+const REGISTRY_FILE_NAME: &str = "mounts.json";
+/// 锁文件名。读改写挂载记录期间独占打开它，避免同时挂载或卸载时互相覆盖记录。
+const LOCK_FILE_NAME: &str = "mounts.json.lock";
+/// 等待其他进程释放锁的间隔和最长时间。
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// 文件正被其他句柄独占打开时的 Windows 错误码。
+const ERROR_SHARING_VIOLATION: i32 = 32;
+/// 删除挂载文件夹的尝试次数和间隔。
+const FOLDER_REMOVAL_ATTEMPTS: u32 = 20;
+const FOLDER_REMOVAL_INTERVAL: Duration = Duration::from_millis(100);
+/// 挂载进程的映像名，用于排除 PID 被其他进程复用的情况。
+const DWARFS_IMAGE_NAME: &str = "dwarfs.exe";
+
+/// 一次由本程序启动的挂载。
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MountRecord {
+    /// 归档的绝对路径
+    pub archive: PathBuf,
+    /// 盘符（如 `Z:`）或挂载文件夹的绝对路径
+    pub mountpoint: String,
+    /// dwarfs.exe 的进程号
+    pub pid: u32,
+    /// 开始挂载的 Unix 时间戳（秒）
+    pub started: u64,
+    /// 卸载后删除挂载文件夹（挂载到归档旁边的文件夹时）
+    #[serde(default)]
+    pub remove_folder: bool,
+}
+
+/// 是否为 `X:` 形式的盘符。
+pub fn is_drive_letter(dest: &str) -> bool {
+    let bytes = dest.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Windows 路径不区分大小写。
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+/// 把挂载目标规范化为记录中的形式：盘符大写，文件夹取绝对路径。
+pub fn normalize_mountpoint(dest: &str) -> Result<String> {
+    let trimmed = dest.trim_end_matches(['\\', '/']);
+    if is_drive_letter(trimmed) {
+        return Ok(trimmed.to_ascii_uppercase());
+    }
+    let folder = if trimmed.is_empty() {
+        dest
+    } else {
+        trimmed
+    };
+    Ok(path::absolute(folder)?.to_string_lossy().into_owned())
+}
+
+impl MountRecord {
+    pub fn new(archive: &Path, mountpoint: String, pid: u32, remove_folder: bool) -> Result<Self> {
+        Ok(Self {
+            archive: path::absolute(archive)?,
+            mountpoint,
+            pid,
+            started: now(),
+            remove_folder,
+        })
+    }
+
+    /// 挂载结束后删除挂载文件夹。WinFsp 移除卷稍有延迟，期间文件夹无法删除，因此会重试几次；
+    /// 文件夹中有内容时不会删除。
+    pub fn remove_mount_folder(&self) {
+        if !self.remove_folder {
+            return;
+        }
+        for _ in 0..FOLDER_REMOVAL_ATTEMPTS {
+            match fs::remove_dir(&self.mountpoint) {
+                Ok(()) => return,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+                Err(_) => thread::sleep(FOLDER_REMOVAL_INTERVAL),
+            }
+        }
+        eprintln!("Failed to remove mount folder {}", self.mountpoint);
+    }
+
+    /// `target` 为该挂载的盘符、挂载文件夹或归档路径时返回 `true`。
+    pub fn matches(&self, target: &str) -> bool {
+        let Ok(target) = normalize_mountpoint(target) else {
+            return false;
+        };
+        if is_drive_letter(&target) {
+            return self.mountpoint == target;
+        }
+        let target = Path::new(&target);
+        same_path(target, &self.archive) || same_path(target, Path::new(&self.mountpoint))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn registry_path() -> PathBuf {
+    data_dir().unwrap_or_else(temp_dir).join(REGISTRY_FILE_NAME)
+}
+
+fn read_records(path: &Path) -> Result<Vec<MountRecord>> {
+    match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid mount registry: {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// 独占打开锁文件，返回的文件关闭时释放锁。进程异常退出时句柄也会随之关闭。
+fn lock_registry(path: &Path) -> Result<File> {
+    let dir = path
+        .parent()
+        .context("Mount registry has no parent folder")?;
+    fs::create_dir_all(dir)?;
+    let lock_path = dir.join(LOCK_FILE_NAME);
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(false).write(true);
+    // 不与其他句柄共享，锁被占用时打开会以共享冲突失败
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::share_mode(&mut options, 0);
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+    loop {
+        match options.open(&lock_path) {
+            Ok(lock) => return Ok(lock),
+            Err(e)
+                if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION)
+                    && Instant::now() < deadline =>
+            {
+                thread::sleep(LOCK_RETRY_INTERVAL);
+            },
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()));
+            },
+        }
+    }
+}
+
+/// 原子地写入记录，避免其他进程读到写了一半的文件。
+fn write_records(path: &Path, records: &[MountRecord]) -> Result<()> {
+    let dir = path
+        .parent()
+        .context("Mount registry has no parent folder")?;
+    fs::create_dir_all(dir)?;
+    let mut file = NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut file, records)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// 进程是否仍在运行且确实是 dwarfs.exe。无法运行 tasklist 时不知道，返回 `None`。
+pub fn process_alive(pid: u32) -> Option<bool> {
+    let output = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| is_dwarfs_task(line, pid)),
+    )
+}
+
+/// 解析 `tasklist /FO CSV /NH` 的一行，形如 `"dwarfs.exe","1234","Console","1","12,345 K"`。
+fn is_dwarfs_task(line: &str, pid: u32) -> bool {
+    let mut fields = line.split(',').map(|field| field.trim().trim_matches('"'));
+    fields
+        .next()
+        .is_some_and(|name| name.eq_ignore_ascii_case(DWARFS_IMAGE_NAME))
+        && fields.next() == Some(pid.to_string().as_str())
+}
+
+/// 把记录分为应当保留的和进程确定已退出的。无法确定进程状态的记录会保留。
+fn prune(
+    records: Vec<MountRecord>,
+    alive: impl Fn(u32) -> Option<bool>,
+) -> (Vec<MountRecord>, Vec<MountRecord>) {
+    records
+        .into_iter()
+        .partition(|record| alive(record.pid) != Some(false))
+}
+
+/// 从文件中删除已退出的挂载，并清理它们留下的挂载文件夹。
+fn remove_dead(path: &Path, alive: &[MountRecord], dead: &[MountRecord]) -> Result<()> {
+    if dead.is_empty() {
+        return Ok(());
+    }
+    write_records(path, alive)?;
+    for record in dead {
+        record.remove_mount_folder();
+    }
+    Ok(())
+}
+
+/// 仍然有效的挂载记录。进程已退出的记录会从文件中删除，调用者须持有锁。
+fn active_mounts(path: &Path) -> Result<Vec<MountRecord>> {
+    let (alive, dead) = prune(read_records(path)?, process_alive);
+    remove_dead(path, &alive, &dead)?;
+    Ok(alive)
+}
+
+/// 一条挂载记录及其当前状态，`mounts --json` 时原样序列化输出。
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MountStatus {
+    #[serde(flatten)]
+    pub record: MountRecord,
+    /// 已挂载的秒数
+    pub uptime: u64,
+    /// dwarfs 进程是否仍在运行，无法确定时为空
+    pub alive: Option<bool>,
+}
+
+/// 所有挂载记录及其状态。已退出的挂载会在这次显示后从文件中删除。
+pub fn mount_statuses() -> Result<Vec<MountStatus>> {
+    let path = registry_path();
+    let _lock = lock_registry(&path)?;
+    let records = read_records(&path)?;
+    let now = now();
+    let statuses: Vec<_> = records
+        .into_iter()
+        .map(|record| MountStatus {
+            uptime: now.saturating_sub(record.started),
+            alive: process_alive(record.pid),
+            record,
+        })
+        .collect();
+    let records = |dead: bool| {
+        statuses
+            .iter()
+            .filter(|status| (status.alive == Some(false)) == dead)
+            .map(|status| status.record.clone())
+            .collect::<Vec<_>>()
+    };
+    remove_dead(&path, &records(false), &records(true))?;
+    Ok(statuses)
+}
+
+/// 把秒数格式化为 `1d 2h`、`3h 04m`、`5m 06s` 或 `7s`。
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        86_400.. => format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600),
+        3600.. => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+        60.. => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{secs}s"),
+    }
+}
+
+/// 以表格形式输出挂载状态。
+pub fn print_mounts(statuses: &[MountStatus]) {
+    let rows: Vec<_> = statuses
+        .iter()
+        .map(|status| {
+            [
+                status.record.mountpoint.clone(),
+                format_uptime(status.uptime),
+                match status.alive {
+                    Some(true) => "running",
+                    Some(false) => "exited",
+                    None => "unknown",
+                }
+                .to_string(),
+                status.record.archive.display().to_string(),
+            ]
+        })
+        .collect();
+    let header = ["MOUNTPOINT", "UPTIME", "STATUS", "ARCHIVE"].map(str::to_string);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+}
+
+/// 记录一次新的挂载。
+pub fn register(record: MountRecord) -> Result<()> {
+    let path = registry_path();
+    let _lock = lock_registry(&path)?;
+    let mut records = active_mounts(&path)?;
+    records.push(record);
+    write_records(&path, &records)
+}
+
+/// 删除某个进程的记录，返回记录是否还存在（不存在说明已经通过 `unmount` 卸载）。
+pub fn unregister(pid: u32) -> Result<bool> {
+    let path = registry_path();
+    let _lock = lock_registry(&path)?;
+    let mut records = read_records(&path)?;
+    let before = records.len();
+    records.retain(|record| record.pid != pid);
+    if records.len() == before {
+        return Ok(false);
+    }
+    write_records(&path, &records)?;
+    Ok(true)
+}
+
+/// 卸载盘符、挂载文件夹或归档路径对应的所有挂载。
+///
+/// dwarfs 是只读文件系统，结束挂载进程后 WinFsp 会移除卷，不会丢失数据。
+pub fn unmount(target: &str) -> Result<()> {
+    let path = registry_path();
+    let lock = lock_registry(&path)?;
+    let (matched, rest): (Vec<_>, Vec<_>) = active_mounts(&path)?
+        .into_iter()
+        .partition(|record| record.matches(target));
+    if matched.is_empty() {
+        bail!("No mount started by this tool matches `{target}`");
+    }
+    // 先删除记录，挂载进程据此判断是被卸载而不是出错退出
+    write_records(&path, &rest)?;
+    drop(lock);
+    for record in matched {
+        let status = Command::new("taskkill")
+            .args(["/PID", &record.pid.to_string(), "/F"])
+            .stdout(Stdio::null())
+            .status()?;
+        ensure!(
+            status.success() || process_alive(record.pid) == Some(false),
+            "Failed to stop dwarfs process {}: taskkill exited with {status}",
+            record.pid
+        );
+        record.remove_mount_folder();
+        println!(
+            "Unmounted {} from `{}`",
+            record.archive.display(),
+            record.mountpoint
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(archive: &str, mountpoint: &str, pid: u32) -> MountRecord {
+        MountRecord {
+            archive: path::absolute(archive).unwrap(),
+            mountpoint: normalize_mountpoint(mountpoint).unwrap(),
+            pid,
+            started: 0,
+            remove_folder: false,
+        }
+    }
+
+    #[test]
+    fn records_match_drive_folder_or_archive() {
+        let drive = record("data/a.dwarfs", "z:", 1);
+        assert!(drive.mountpoint == "Z:");
+        assert!(drive.matches("Z:") && drive.matches("z:\\"));
+        assert!(drive.matches("data/A.dwarfs") && !drive.matches("Y:"));
+        let folder = record("b.dwarfs", "mnt/b", 2);
+        assert!(folder.matches("mnt/b/") && !folder.matches("mnt"));
+    }
+
+    #[test]
+    fn registry_round_trips_and_prunes_dead_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join(REGISTRY_FILE_NAME);
+        assert!(read_records(&path).unwrap().is_empty());
+        let records = vec![record("a.dwarfs", "Z:", 1), record("b.dwarfs", "Y:", 2)];
+        write_records(&path, &records).unwrap();
+        assert!(read_records(&path).unwrap() == records);
+        let (alive, dead) = prune(records.clone(), |pid| Some(pid == 2));
+        assert!(alive.len() == 1 && alive[0].pid == 2 && dead[0].pid == 1);
+        // tasklist 无法运行时不能断定进程已退出
+        let (kept, dead) = prune(records, |pid| (pid == 2).then_some(true));
+        assert!(kept.len() == 2 && dead.is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn registry_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join(REGISTRY_FILE_NAME);
+        let lock_path = dir.path().join("sub").join(LOCK_FILE_NAME);
+        let lock = lock_registry(&path).unwrap();
+        let error = File::open(&lock_path).unwrap_err();
+        assert!(error.raw_os_error() == Some(ERROR_SHARING_VIOLATION));
+        drop(lock);
+        assert!(File::open(&lock_path).is_ok());
+    }
+
+    #[test]
+    fn uptime_is_formatted_with_two_units() {
+        assert!(format_uptime(7) == "7s");
+        assert!(format_uptime(366) == "6m 06s");
+        assert!(format_uptime(3 * 3600 + 4 * 60 + 5) == "3h 04m");
+        assert!(format_uptime(86_400 + 2 * 3600) == "1d 2h");
+    }
+
+    #[test]
+    fn tasklist_lines_are_checked_by_name_and_pid() {
+        assert!(is_dwarfs_task(
+            r#""dwarfs.exe","1234","Console","1","12,345 K""#,
+            1234
+        ));
+        assert!(!is_dwarfs_task(
+            r#""notepad.exe","1234","Console","1","1 K""#,
+            1234
+        ));
+        assert!(!is_dwarfs_task("INFO: No tasks are running", 1234));
+    }
+}
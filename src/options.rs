@@ -0,0 +1,706 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, bail, ensure};
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+
+/// mkdwarfs 支持的压缩算法名。
+const KNOWN_ALGORITHMS: [&str; 8] = [
+    "null", "zstd", "lzma", "lz4", "lz4hc", "brotli", "flac", "ricepp",
+];
+
+/// inode 排序方式，对应 mkdwarfs 的 `--order`。
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InodeOrder {
+    None,
+    Path,
+    Revpath,
+    Similarity,
+    Nilsimsa,
+}
+
+impl InodeOrder {
+    const fn as_arg(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Path => "path",
+            Self::Revpath => "revpath",
+            Self::Similarity => "similarity",
+            Self::Nilsimsa => "nilsimsa",
+        }
+    }
+}
+
+/// 重新压缩镜像的哪些部分，对应 mkdwarfs 的 `--recompress`。
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecompressSection {
+    /// File data blocks and metadata
+    #[default]
+    All,
+    /// File data blocks only
+    Block,
+    /// Metadata only
+    Metadata,
+}
+
+impl RecompressSection {
+    pub const fn as_arg(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Block => "block",
+            Self::Metadata => "metadata",
+        }
+    }
+}
+
+/// dwarfs 的日志级别，对应 `-o debuglevel=`。
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Verbose,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    const fn as_arg(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Verbose => "verbose",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// dwarfs 的挂载参数。所有字段均为可选，未设置的项使用 dwarfs 的默认值。
+///
+/// 同一结构既是命令行参数，也是配置文件中 `[mount]` 表的格式（键名为 kebab-case）。
+#[derive(Args, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MountOptions {
+    /// Size of the block cache, e.g. `512m` or `2g`
+    #[arg(long, value_name = "SIZE")]
+    pub cache_size: Option<String>,
+    /// Number of worker threads that decompress blocks
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub workers: Option<u32>,
+    /// How much data to read ahead when a file is read sequentially, e.g. `4m`
+    #[arg(long, value_name = "SIZE")]
+    pub readahead: Option<String>,
+    /// Look up file names case-insensitively, like NTFS. Use `--case-insensitive=false` to turn
+    /// it off when the config file enables it.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub case_insensitive: Option<bool>,
+    /// Volume name shown in Explorer
+    #[arg(long)]
+    pub volume_name: Option<String>,
+    /// How much dwarfs logs
+    #[arg(long, value_enum)]
+    pub log_level: Option<LogLevel>,
+}
+
+/// 校验 dwarfs 的大小参数：数字加可选的 `k`/`m`/`g`/`t` 后缀。
+fn validate_size(name: &str, size: &str) -> Result<()> {
+    let digits = size
+        .strip_suffix(['k', 'm', 'g', 't', 'K', 'M', 'G', 'T'])
+        .unwrap_or(size);
+    ensure!(
+        !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()),
+        "Invalid {name} `{size}`, expected a number with an optional k, m, g or t suffix"
+    );
+    Ok(())
+}
+
+impl MountOptions {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(size) = &self.cache_size {
+            validate_size("cache size", size)?;
+        }
+        if let Some(size) = &self.readahead {
+            validate_size("readahead", size)?;
+        }
+        if self.workers == Some(0) {
+            bail!("Worker count must be at least 1");
+        }
+        if let Some(name) = &self.volume_name {
+            // `-o` 的各项以逗号分隔
+            ensure!(
+                !name.is_empty() && !name.contains([',', '\n', '\r']),
+                "Invalid volume name `{name}`, it must not be empty or contain commas"
+            );
+        }
+        Ok(())
+    }
+
+    /// 以 `self` 中已设置的项为准，未设置的项取 `fallback` 中的值。用于让命令行参数覆盖配置文件。
+    #[must_use]
+    pub fn or(self, fallback: &Self) -> Self {
+        Self {
+            cache_size: self.cache_size.or_else(|| fallback.cache_size.clone()),
+            workers: self.workers.or(fallback.workers),
+            readahead: self.readahead.or_else(|| fallback.readahead.clone()),
+            case_insensitive: self.case_insensitive.or(fallback.case_insensitive),
+            volume_name: self.volume_name.or_else(|| fallback.volume_name.clone()),
+            log_level: self.log_level.or(fallback.log_level),
+        }
+    }
+
+    /// 转换为 dwarfs 的 `-o` 参数。调用前应先 [`Self::validate`]。
+    pub fn to_args(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(size) = &self.cache_size {
+            options.push(format!("cachesize={size}"));
+        }
+        if let Some(workers) = self.workers {
+            options.push(format!("workers={workers}"));
+        }
+        if let Some(size) = &self.readahead {
+            options.push(format!("readahead={size}"));
+        }
+        if self.case_insensitive == Some(true) {
+            options.push("case_insensitive".to_string());
+        }
+        if let Some(name) = &self.volume_name {
+            options.push(format!("volname={name}"));
+        }
+        if let Some(level) = self.log_level {
+            options.push(format!("debuglevel={}", level.as_arg()));
+        }
+        options
+            .into_iter()
+            .flat_map(|option| ["-o".to_string(), option])
+            .collect()
+    }
+}
+
+/// mkdwarfs 的压缩参数。所有字段均为可选，未设置的项沿用 mkdwarfs 按压缩级别选择的默认值。
+///
+/// 同一结构既是命令行参数，也是配置文件中预设的格式（键名为 kebab-case）。
+#[derive(Args, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CompressOptions {
+    /// Compression level (0-9, default 7)
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub compression_level: Option<u8>,
+    /// Block size as a power of two (10-30)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(10..=30))]
+    pub block_size_bits: Option<u8>,
+    /// Segmenting window size as a power of two (0-30, 0 disables segmenting)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=30))]
+    pub window_size: Option<u8>,
+    /// Segmenting window step as a power of two (0-8)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=8))]
+    pub window_step: Option<u8>,
+    /// Inode ordering used to group similar files
+    #[arg(long, value_enum)]
+    pub order: Option<InodeOrder>,
+    /// Block data compression, e.g. `zstd:level=19` or `lzma:level=9`
+    #[arg(long)]
+    pub block_compression: Option<String>,
+    /// Schema compression, e.g. `zstd:level=16`
+    #[arg(long)]
+    pub schema_compression: Option<String>,
+    /// Metadata compression, e.g. `zstd:level=22` or `null`
+    #[arg(long)]
+    pub metadata_compression: Option<String>,
+    /// History compression, e.g. `zstd:level=16`
+    #[arg(long)]
+    pub history_compression: Option<String>,
+    /// Number of worker threads (default: number of CPUs)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub workers: Option<u32>,
+    /// Exclude paths matching this glob, e.g. `node_modules`, `*.tmp` or `/build/` (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Keep paths matching this glob even if they are excluded (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+    /// File with one mkdwarfs filter rule per line (`+ GLOB` includes, `- GLOB` excludes, `#`
+    /// starts a comment)
+    #[arg(long)]
+    pub filter_file: Option<PathBuf>,
+}
+
+/// 校验单条 glob，mkdwarfs 的过滤规则按行分隔，不能为空或跨行。
+fn validate_glob(kind: &str, glob: &str) -> Result<()> {
+    ensure!(
+        !glob.trim().is_empty() && !glob.contains(['\n', '\r']),
+        "Invalid {kind} pattern `{glob}`"
+    );
+    Ok(())
+}
+
+/// 解析过滤规则文件的内容，返回 `+ GLOB` / `- GLOB` 形式的规则。
+fn parse_filter_file(content: &str) -> Result<Vec<String>> {
+    let mut rules = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(' ') {
+            Some(("+" | "-", glob)) if !glob.trim().is_empty() => {
+                rules.push(line.to_string());
+            },
+            _ => bail!(
+                "Invalid filter rule on line {}: `{line}`, expected `+ GLOB` or `- GLOB`",
+                number + 1
+            ),
+        }
+    }
+    Ok(rules)
+}
+
+/// 校验形如 `algo[:key=value...]` 的压缩算法描述。
+fn validate_compression(section: &str, spec: &str) -> Result<()> {
+    let mut parts = spec.split(':');
+    let algorithm = parts.next().unwrap_or_default();
+    ensure!(
+        KNOWN_ALGORITHMS.contains(&algorithm),
+        "Unknown {section} compression algorithm `{algorithm}`, expected one of: {}",
+        KNOWN_ALGORITHMS.join(", ")
+    );
+    for option in parts {
+        let Some((key, value)) = option.split_once('=') else {
+            bail!("Invalid {section} compression option `{option}`, expected `key=value`");
+        };
+        ensure!(
+            !key.is_empty() && !value.is_empty(),
+            "Invalid {section} compression option `{option}`, expected `key=value`"
+        );
+    }
+    Ok(())
+}
+
+/// 解压时只提取部分内容，对应 dwarfsextract 的 `--pattern`。两者都为空时提取全部内容。
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractFilter {
+    /// Only extract entries matching this glob, relative to the archive root, e.g. `**/*.txt`
+    /// (repeatable)
+    #[arg(long = "pattern", value_name = "GLOB")]
+    pub patterns: Vec<String>,
+    /// Only extract this file or folder (with everything below it), relative to the archive root
+    /// (repeatable)
+    #[arg(long = "path", value_name = "PATH")]
+    pub paths: Vec<String>,
+}
+
+/// 转义 glob 中的特殊字符，使路径按字面匹配。
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '*' | '?' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl ExtractFilter {
+    /// 生成 dwarfsextract 的 `--pattern` 参数。
+    ///
+    /// `--path` 转换为转义后的字面路径本身及其下的 `/**`，以便同时匹配文件和整个文件夹。
+    pub fn to_args(&self) -> Result<Vec<String>> {
+        let mut patterns = Vec::new();
+        for glob in &self.patterns {
+            validate_glob("extract", glob)?;
+            patterns.push(glob.clone());
+        }
+        for path in &self.paths {
+            let normalized = path.replace('\\', "/");
+            let normalized = normalized.trim_matches('/');
+            ensure!(
+                !normalized.is_empty()
+                    && !normalized.contains(['\n', '\r'])
+                    && !normalized
+                        .split('/')
+                        .any(|part| part == ".." || part == "."),
+                "Invalid extract path `{path}`"
+            );
+            let escaped = escape_glob(normalized);
+            patterns.push(format!("{escaped}/**"));
+            patterns.push(escaped);
+        }
+        Ok(patterns
+            .into_iter()
+            .map(|pattern| format!("--pattern={pattern}"))
+            .collect())
+    }
+}
+
+impl CompressOptions {
+    /// 校验参数之间的约束。clap 已检查单个值的范围，这里补充库调用时的检查。
+    pub fn validate(&self) -> Result<()> {
+        if let Some(level) = self.compression_level {
+            ensure!(level <= 9, "Compression level must be 0-9, got {level}");
+        }
+        if let Some(bits) = self.block_size_bits {
+            ensure!(
+                (10..=30).contains(&bits),
+                "Block size bits must be 10-30, got {bits}"
+            );
+        }
+        if let Some(step) = self.window_step {
+            ensure!(step <= 8, "Window step must be 0-8, got {step}");
+        }
+        if let (Some(window), Some(block)) = (self.window_size, self.block_size_bits) {
+            ensure!(
+                window <= block,
+                "Window size ({window}) must not exceed block size bits ({block})"
+            );
+        }
+        if self.workers == Some(0) {
+            bail!("Worker count must be at least 1");
+        }
+        for (section, spec) in [
+            ("block", &self.block_compression),
+            ("schema", &self.schema_compression),
+            ("metadata", &self.metadata_compression),
+            ("history", &self.history_compression),
+        ] {
+            if let Some(spec) = spec {
+                validate_compression(section, spec)?;
+            }
+        }
+        for glob in &self.exclude {
+            validate_glob("exclude", glob)?;
+        }
+        for glob in &self.include {
+            validate_glob("include", glob)?;
+        }
+        if let Some(filter_file) = &self.filter_file {
+            ensure!(
+                filter_file.is_file(),
+                "Filter file does not exist: {}",
+                filter_file.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// 以 `self` 中已设置的项为准，未设置的项取 `fallback` 中的值。
+    ///
+    /// 用于让命令行参数覆盖预设。过滤规则则是合并：预设中的规则在前，命令行的规则在后。
+    #[must_use]
+    pub fn or(self, fallback: &Self) -> Self {
+        Self {
+            compression_level: self.compression_level.or(fallback.compression_level),
+            block_size_bits: self.block_size_bits.or(fallback.block_size_bits),
+            window_size: self.window_size.or(fallback.window_size),
+            window_step: self.window_step.or(fallback.window_step),
+            order: self.order.or(fallback.order),
+            block_compression: self
+                .block_compression
+                .or_else(|| fallback.block_compression.clone()),
+            schema_compression: self
+                .schema_compression
+                .or_else(|| fallback.schema_compression.clone()),
+            metadata_compression: self
+                .metadata_compression
+                .or_else(|| fallback.metadata_compression.clone()),
+            history_compression: self
+                .history_compression
+                .or_else(|| fallback.history_compression.clone()),
+            workers: self.workers.or(fallback.workers),
+            exclude: [fallback.exclude.as_slice(), &self.exclude].concat(),
+            include: [fallback.include.as_slice(), &self.include].concat(),
+            filter_file: self.filter_file.or_else(|| fallback.filter_file.clone()),
+        }
+    }
+
+    /// 是否设置了任何过滤规则。
+    pub fn has_filters(&self) -> bool {
+        !self.exclude.is_empty() || !self.include.is_empty() || self.filter_file.is_some()
+    }
+
+    /// 生成 mkdwarfs 的 `-F` 参数。
+    ///
+    /// mkdwarfs 按顺序取第一条匹配的规则，因此 `--include` 排在 `--exclude` 之前以便作为例外，
+    /// 命令行规则排在过滤规则文件之前。
+    pub fn filter_args(&self) -> Result<Vec<String>> {
+        let mut rules = Vec::new();
+        rules.extend(self.include.iter().map(|glob| format!("+ {glob}")));
+        rules.extend(self.exclude.iter().map(|glob| format!("- {glob}")));
+        if let Some(filter_file) = &self.filter_file {
+            let content = fs::read_to_string(filter_file)
+                .with_context(|| format!("Failed to read filter file {}", filter_file.display()))?;
+            rules.extend(
+                parse_filter_file(&content)
+                    .with_context(|| format!("Invalid filter file {}", filter_file.display()))?,
+            );
+        }
+        Ok(rules
+            .into_iter()
+            .flat_map(|rule| ["-F".to_string(), rule])
+            .collect())
+    }
+
+    /// 重新压缩时无法改变的选项的参数名：块划分、文件顺序和包含的文件已固定在镜像中。
+    pub fn layout_options(&self) -> Vec<&'static str> {
+        [
+            ("--block-size-bits", self.block_size_bits.is_some()),
+            ("--window-size", self.window_size.is_some()),
+            ("--window-step", self.window_step.is_some()),
+            ("--order", self.order.is_some()),
+            ("filters", self.has_filters()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+
+    /// 重新压缩时的 mkdwarfs 参数，只包含压缩级别、各部分的压缩算法和线程数。
+    pub fn recompress_args(&self) -> Vec<String> {
+        Self {
+            block_size_bits: None,
+            window_size: None,
+            window_step: None,
+            order: None,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            filter_file: None,
+            ..self.clone()
+        }
+        .to_args()
+    }
+
+    /// 转换为 mkdwarfs 命令行参数。调用前应先 [`Self::validate`]。
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        if let Some(level) = self.compression_level {
+            push("-l", level.to_string());
+        }
+        if let Some(bits) = self.block_size_bits {
+            push("-S", bits.to_string());
+        }
+        if let Some(window) = self.window_size {
+            push("-W", window.to_string());
+        }
+        if let Some(step) = self.window_step {
+            push("-w", step.to_string());
+        }
+        if let Some(order) = self.order {
+            push("--order", order.as_arg().to_string());
+        }
+        if let Some(spec) = &self.block_compression {
+            push("-C", spec.clone());
+        }
+        if let Some(spec) = &self.schema_compression {
+            push("--schema-compression", spec.clone());
+        }
+        if let Some(spec) = &self.metadata_compression {
+            push("--metadata-compression", spec.clone());
+        }
+        if let Some(spec) = &self.history_compression {
+            push("--history-compression", spec.clone());
+        }
+        if let Some(workers) = self.workers {
+            push("-N", workers.to_string());
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_options_produce_no_args() {
+        let options = CompressOptions::default();
+        assert!(options.validate().is_ok());
+        assert!(options.to_args().is_empty());
+    }
+
+    #[test]
+    fn options_are_turned_into_mkdwarfs_args() {
+        let options = CompressOptions {
+            compression_level: Some(9),
+            block_size_bits: Some(24),
+            window_size: Some(12),
+            window_step: Some(3),
+            order: Some(InodeOrder::Nilsimsa),
+            metadata_compression: Some("zstd:level=22".to_string()),
+            workers: Some(4),
+            ..Default::default()
+        };
+        assert!(options.validate().is_ok());
+        assert!(
+            options.to_args()
+                == [
+                    "-l",
+                    "9",
+                    "-S",
+                    "24",
+                    "-W",
+                    "12",
+                    "-w",
+                    "3",
+                    "--order",
+                    "nilsimsa",
+                    "--metadata-compression",
+                    "zstd:level=22",
+                    "-N",
+                    "4"
+                ]
+        );
+    }
+
+    #[test]
+    fn explicit_options_override_fallback() {
+        let cli = CompressOptions {
+            compression_level: Some(3),
+            ..Default::default()
+        };
+        let preset = CompressOptions {
+            compression_level: Some(9),
+            order: Some(InodeOrder::Similarity),
+            ..Default::default()
+        };
+        let merged = cli.or(&preset);
+        assert!(merged.compression_level == Some(3));
+        assert!(merged.order == Some(InodeOrder::Similarity));
+    }
+
+    #[test]
+    fn filters_become_ordered_mkdwarfs_rules() {
+        let preset = CompressOptions {
+            exclude: vec!["node_modules".to_string()],
+            ..Default::default()
+        };
+        let cli = CompressOptions {
+            exclude: vec!["*.tmp".to_string()],
+            include: vec!["keep.tmp".to_string()],
+            ..Default::default()
+        };
+        let merged = cli.or(&preset);
+        assert!(merged.has_filters());
+        assert!(
+            merged.filter_args().unwrap()
+                == ["-F", "+ keep.tmp", "-F", "- node_modules", "-F", "- *.tmp"]
+        );
+    }
+
+    #[test]
+    fn filter_file_rules_are_parsed_strictly() {
+        let rules = parse_filter_file("# build output\n- /target/\n\n+ *.rs\n").unwrap();
+        assert!(rules == ["- /target/", "+ *.rs"]);
+        assert!(parse_filter_file("target\n").is_err());
+        assert!(parse_filter_file("- \n").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_or_unknown_values() {
+        let window_too_large = CompressOptions {
+            block_size_bits: Some(16),
+            window_size: Some(20),
+            ..Default::default()
+        };
+        assert!(window_too_large.validate().is_err());
+
+        let unknown_algorithm = CompressOptions {
+            block_compression: Some("gzip:level=9".to_string()),
+            ..Default::default()
+        };
+        assert!(unknown_algorithm.validate().is_err());
+
+        let malformed_option = CompressOptions {
+            schema_compression: Some("zstd:level".to_string()),
+            ..Default::default()
+        };
+        assert!(malformed_option.validate().is_err());
+    }
+
+    #[test]
+    fn mount_options_become_dwarfs_options() {
+        let config = MountOptions {
+            cache_size: Some("1g".to_string()),
+            case_insensitive: Some(true),
+            log_level: Some(LogLevel::Warn),
+            ..Default::default()
+        };
+        let cli = MountOptions {
+            workers: Some(4),
+            case_insensitive: Some(false),
+            volume_name: Some("Backup 2024".to_string()),
+            ..Default::default()
+        };
+        let merged = cli.or(&config);
+        assert!(merged.validate().is_ok());
+        assert!(
+            merged.to_args()
+                == [
+                    "-o",
+                    "cachesize=1g",
+                    "-o",
+                    "workers=4",
+                    "-o",
+                    "volname=Backup 2024",
+                    "-o",
+                    "debuglevel=warn"
+                ]
+        );
+        assert!(MountOptions::default().to_args().is_empty());
+        for invalid in [
+            MountOptions {
+                cache_size: Some("lots".to_string()),
+                ..Default::default()
+            },
+            MountOptions {
+                volume_name: Some("a,b".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn extract_paths_become_literal_patterns() {
+        let filter = ExtractFilter {
+            patterns: vec!["**/*.txt".to_string()],
+            paths: vec!["\\docs\\a[1]\\".to_string()],
+        };
+        assert!(
+            filter.to_args().unwrap()
+                == [
+                    "--pattern=**/*.txt",
+                    "--pattern=docs/a\\[1\\]/**",
+                    "--pattern=docs/a\\[1\\]"
+                ]
+        );
+        let filter = ExtractFilter {
+            paths: vec!["../outside".to_string()],
+            ..ExtractFilter::default()
+        };
+        assert!(filter.to_args().is_err());
+        assert!(ExtractFilter::default().to_args().unwrap().is_empty());
+    }
+
+    #[test]
+    fn recompress_keeps_only_compression_args() {
+        let options = CompressOptions {
+            compression_level: Some(9),
+            block_size_bits: Some(24),
+            order: Some(InodeOrder::Path),
+            metadata_compression: Some("null".to_string()),
+            exclude: vec!["*.tmp".to_string()],
+            ..CompressOptions::default()
+        };
+        assert!(options.layout_options() == ["--block-size-bits", "--order", "filters"]);
+        assert!(options.recompress_args() == ["-l", "9", "--metadata-compression", "null"]);
+        assert!(RecompressSection::default().as_arg() == "all");
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    fmt::{self, Write as _},
+    io::{self, BufRead, IsTerminal, Write},
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// 进度输出方式。
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Single-line progress bar on stderr
+    #[default]
+    Bar,
+    /// Newline-delimited JSON events on stdout
+    Json,
+    /// Pass the child's own output through unchanged
+    Raw,
+}
+
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+/// 设置进程级的进度输出方式，只在启动时调用一次。
+pub fn set_mode(mode: ProgressMode) {
+    let _ = MODE.set(mode);
+}
+
+/// 当前的进度输出方式，未设置时为 [`ProgressMode::Bar`]。
+pub fn mode() -> ProgressMode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+/// 输出给用户看的状态信息。JSON 模式下 stdout 只留给进度事件，状态信息改写到 stderr。
+pub fn status(message: impl fmt::Display) {
+    if mode() == ProgressMode::Json {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// 子进程所处的阶段。
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    #[default]
+    Starting,
+    Scanning,
+    Ordering,
+    Compressing,
+    Metadata,
+    Extracting,
+    Done,
+}
+
+impl Phase {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Scanning => "scanning",
+            Self::Ordering => "ordering",
+            Self::Compressing => "compressing",
+            Self::Metadata => "metadata",
+            Self::Extracting => "extracting",
+            Self::Done => "done",
+        }
+    }
+}
+
+/// 从 mkdwarfs/dwarfsextract 输出中累积出的进度状态。
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    pub phase: Phase,
+    pub files_scanned: Option<u64>,
+    pub files_total: Option<u64>,
+    pub bytes_in: Option<u64>,
+    pub bytes_out: Option<u64>,
+    pub percent: Option<f64>,
+    pub eta_secs: Option<u64>,
+}
+
+/// 去掉 dwarfs 日志行的 `I 12:34:56.789012 ` 前缀。
+fn strip_log_prefix(line: &str) -> &str {
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(level), Some(time), Some(rest))
+            if level.len() == 1
+                && "IWEVDT".contains(level)
+                && time.contains(':')
+                && time
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || b == b':' || b == b'.') =>
+        {
+            rest
+        },
+        _ => line,
+    }
+}
+
+/// 解析 `1.5 GiB`、`300MiB`、`12 KB` 这类大小。`unit` 为空时表示 `value` 自带单位。
+fn parse_size(value: &str, unit: &str) -> Option<u64> {
+    let (number, unit) = if unit.is_empty() {
+        let split = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        value.split_at(split)
+    } else {
+        (value, unit)
+    };
+    let multiplier: u64 = match unit.trim_end_matches([',', ')']) {
+        "B" | "bytes" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        _ => return None,
+    };
+    let number: f64 = number.parse().ok()?;
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    Some((number * multiplier as f64).round() as u64)
+}
+
+/// 解析 `1:02:03`、`02:03`、`45s` 形式的剩余时间。
+fn parse_duration(value: &str) -> Option<u64> {
+    let value = value.trim_end_matches(',');
+    if let Some(secs) = value.strip_suffix('s') {
+        return secs.parse().ok();
+    }
+    if !value.contains(':') {
+        return None;
+    }
+    value
+        .split(':')
+        .try_fold(0u64, |acc, part| Some(acc * 60 + part.parse::<u64>().ok()?))
+}
+
+/// 解析 `123` 或 `123/456` 形式的文件计数。
+fn parse_count(value: &str) -> Option<(u64, Option<u64>)> {
+    match value.split_once('/') {
+        Some((done, total)) => Some((done.parse().ok()?, Some(total.parse().ok()?))),
+        None => Some((value.parse().ok()?, None)),
+    }
+}
+
+/// 逐行解析子进程输出，累积出 [`Progress`]。
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    progress: Progress,
+}
+
+impl ProgressParser {
+    pub const fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// 输入一条记录（以 `\r` 或 `\n` 分隔），返回进度是否因此发生变化。
+    pub fn feed(&mut self, record: &str) -> bool {
+        let before = self.progress.clone();
+        let line = strip_log_prefix(record.trim());
+        self.update_phase(&line.to_lowercase());
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1).copied().unwrap_or_default();
+            let after_next = tokens.get(i + 2).copied().unwrap_or_default();
+            let lower = token.trim_end_matches([',', ':']).to_lowercase();
+            match lower.as_str() {
+                "files" if i > 0 => {
+                    if let Some((done, total)) = parse_count(tokens[i - 1]) {
+                        self.progress.files_scanned = Some(done);
+                        self.progress.files_total = total.or(self.progress.files_total);
+                    }
+                },
+                "eta" => {
+                    if let Some(secs) = parse_duration(next) {
+                        self.progress.eta_secs = Some(secs);
+                    }
+                },
+                "size" if i > 0 && tokens[i - 1].eq_ignore_ascii_case("original") => {
+                    let size = parse_size(next, after_next).or_else(|| parse_size(next, ""));
+                    self.progress.bytes_in = size.or(self.progress.bytes_in);
+                },
+                // `compressed 4.11 GiB to 301.5 MiB`
+                "compressed" => self.parse_compressed_summary(&tokens[i + 1..]),
+                _ => {
+                    // 进度条的百分比紧跟在进度条字符之后，例如 `███▏100%`
+                    if let Some(percent) = token.trim_end_matches(',').strip_suffix('%')
+                        && let Ok(percent) = percent
+                            .trim_start_matches(|c: char| !c.is_ascii_digit())
+                            .parse::<f64>()
+                    {
+                        self.progress.percent = Some(percent.clamp(0.0, 100.0));
+                    }
+                },
+            }
+        }
+        self.progress != before
+    }
+
+    fn update_phase(&mut self, lower: &str) {
+        let phase = if lower.contains("without errors") || lower.contains("extraction finished") {
+            Phase::Done
+        } else if lower.contains("scanning") {
+            Phase::Scanning
+        } else if lower.contains("ordering") || lower.contains("categoriz") {
+            Phase::Ordering
+        } else if lower.contains("metadata") {
+            Phase::Metadata
+        } else if lower.contains("segmenting")
+            || lower.contains("blockif")
+            || lower.contains("compressing")
+        {
+            Phase::Compressing
+        } else if lower.contains("extract") {
+            Phase::Extracting
+        } else {
+            return;
+        };
+        self.progress.phase = phase;
+    }
+
+    fn parse_compressed_summary(&mut self, rest: &[&str]) {
+        let Some(to) = rest.iter().position(|&t| t == "to") else {
+            // 状态块中的 `compressed filesystem: 230 blocks/426.5 MiB written`
+            if let Some(i) = rest.iter().position(|t| t.contains("blocks/")) {
+                let (_, value) = rest[i].split_once("blocks/").unwrap_or_default();
+                let unit = rest.get(i + 1).copied().unwrap_or_default();
+                if let Some(output) = parse_size(value, unit).or_else(|| parse_size(value, "")) {
+                    self.progress.bytes_out = Some(output);
+                }
+            }
+            return;
+        };
+        let size_of = |tokens: &[&str]| match tokens {
+            [value, unit, ..] => parse_size(value, unit).or_else(|| parse_size(value, "")),
+            [value] => parse_size(value, ""),
+            [] => None,
+        };
+        if let (Some(input), Some(output)) = (size_of(&rest[..to]), size_of(&rest[to + 1..])) {
+            self.progress.bytes_in = Some(input);
+            self.progress.bytes_out = Some(output);
+        }
+    }
+}
+
+/// 格式化字节数，例如 `1.5 GiB`。
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// 把进度渲染为单行文本，供进度条使用。
+fn render_bar(progress: &Progress) -> String {
+    const WIDTH: u32 = 20;
+    let mut line = String::new();
+    if let Some(percent) = progress.percent {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let filled = ((percent / 100.0) * f64::from(WIDTH))
+            .round()
+            .min(f64::from(WIDTH)) as usize;
+        let _ = write!(
+            line,
+            "[{}{}] {percent:>5.1}% ",
+            "#".repeat(filled),
+            "-".repeat(WIDTH as usize - filled)
+        );
+    }
+    line.push_str(progress.phase.label());
+    match (progress.files_scanned, progress.files_total) {
+        (Some(done), Some(total)) => {
+            let _ = write!(line, "  {done}/{total} files");
+        },
+        (Some(done), None) => {
+            let _ = write!(line, "  {done} files");
+        },
+        _ => {},
+    }
+    match (progress.bytes_in, progress.bytes_out) {
+        (Some(input), Some(output)) => {
+            let _ = write!(line, "  {} -> {}", format_size(input), format_size(output));
+        },
+        (Some(input), None) => {
+            let _ = write!(line, "  {}", format_size(input));
+        },
+        _ => {},
+    }
+    if let Some(eta) = progress.eta_secs {
+        let _ = write!(
+            line,
+            "  ETA {:02}:{:02}:{:02}",
+            eta / 3600,
+            eta / 60 % 60,
+            eta % 60
+        );
+    }
+    line
+}
+
+/// 按 [`mode`] 输出进度的 reporter。
+pub struct Reporter {
+    mode: ProgressMode,
+    interactive: bool,
+    last_width: usize,
+}
+
+impl Reporter {
+    pub fn new() -> Self {
+        Self {
+            mode: mode(),
+            interactive: io::stderr().is_terminal(),
+            last_width: 0,
+        }
+    }
+
+    pub fn report(&mut self, progress: &Progress) {
+        match self.mode {
+            ProgressMode::Bar => {
+                let line = render_bar(progress);
+                let mut stderr = io::stderr().lock();
+                if self.interactive {
+                    let pad = self.last_width.saturating_sub(line.chars().count());
+                    let _ = write!(stderr, "\r{line}{}", " ".repeat(pad));
+                } else {
+                    let _ = writeln!(stderr, "{line}");
+                }
+                let _ = stderr.flush();
+                self.last_width = line.chars().count();
+            },
+            ProgressMode::Json => {
+                if let Ok(json) = serde_json::to_string(progress) {
+                    let mut stdout = io::stdout().lock();
+                    let _ = writeln!(stdout, "{json}");
+                    let _ = stdout.flush();
+                }
+            },
+            ProgressMode::Raw => {},
+        }
+    }
+
+    /// 结束进度条所在行。
+    pub fn finish(&mut self) {
+        if self.mode == ProgressMode::Bar && self.interactive && self.last_width > 0 {
+            eprintln!();
+        }
+        self.last_width = 0;
+    }
+}
+
+/// 从 `reader` 中按 `\r`/`\n` 拆分记录并逐条回调，进度条和日志都按此分隔。
+pub fn for_each_record(reader: impl BufRead, mut f: impl FnMut(&str)) -> io::Result<()> {
+    for chunk in reader.split(b'\n') {
+        let chunk = chunk?;
+        for record in chunk.split(|&b| b == b'\r') {
+            let record = String::from_utf8_lossy(record);
+            if !record.trim().is_empty() {
+                f(&record);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// dwarfs README 中 mkdwarfs 示例运行的输出（节选），包括日志、状态块和进度条。
+    const MKDWARFS_OUTPUT: &str = "\
+I 11:33:33.310931 scanning /home/mhx/perl-install
+I 11:34:25.263281 waiting for background scanners...
+I 11:34:25.370566 assigning directory and link inodes...
+I 11:34:25.437225 finding duplicate files...
+I 11:34:26.001221 saved 28.2 GiB / 47.65 GiB in 1782826/1927501 duplicate files
+I 11:34:27.298523 building metadata...
+I 11:34:27.299102 ordering 144675 inodes using nilsimsa similarity...
+I 11:35:47.633925 144675 inodes ordered [80.34s]
+I 11:35:47.634004 waiting for segmenting/blockifying to finish...
+I 11:40:57.432155 saving chunks...
+I 11:40:58.051203 waiting for compression to finish...
+I 11:41:13.437006 compressed 47.65 GiB to 426.5 MiB (ratio=0.00874044)
+I 11:41:13.929465 filesystem created without errors [7.659min]
+waiting for block compression to finish
+330733 dirs, 0/2440 soft/hard links, 1927501/1927501 files, 0 other
+original size: 47.65 GiB, dedupe: 28.2 GiB (1782826 files), segment: 15.86 GiB
+filesystem: 3.59 GiB in 230 blocks (319178 chunks, 144675/144675 inodes)
+compressed filesystem: 230 blocks/426.5 MiB written [depth: 20000]
+\u{2588}\u{2588}\u{2588}\u{2588}\u{258f}100% -
+";
+
+    #[test]
+    fn parses_mkdwarfs_output_into_progress() {
+        let mut records = MKDWARFS_OUTPUT.lines();
+        let mut parser = ProgressParser::default();
+        assert!(parser.feed(records.next().unwrap()));
+        assert!(parser.progress().phase == Phase::Scanning);
+        for record in records.by_ref().take(6) {
+            parser.feed(record);
+        }
+        assert!(parser.progress().phase == Phase::Ordering);
+        assert!(parser.progress().files_scanned.is_none());
+        for record in records {
+            parser.feed(record);
+        }
+        let progress = parser.progress();
+        assert!(progress.phase == Phase::Done);
+        assert!(progress.files_scanned == Some(1_927_501));
+        assert!(progress.files_total == Some(1_927_501));
+        assert!(progress.bytes_in == parse_size("47.65", "GiB"));
+        assert!(progress.bytes_out == parse_size("426.5", "MiB"));
+        assert!(progress.percent == Some(100.0));
+    }
+
+    #[test]
+    fn status_block_reports_written_size() {
+        let mut parser = ProgressParser::default();
+        assert!(parser.feed("compressed filesystem: 12/230 blocks/1.5 MiB written"));
+        assert!(parser.progress().bytes_out == Some(3 << 19));
+        assert!(parser.feed("42%, ETA 00:01:30"));
+        assert!(parser.progress().percent == Some(42.0) && parser.progress().eta_secs == Some(90));
+    }
+
+    #[test]
+    fn unrelated_lines_do_not_change_progress() {
+        let mut parser = ProgressParser::default();
+        assert!(!parser.feed("some unrelated output"));
+        assert!(*parser.progress() == Progress::default());
+    }
+
+    #[test]
+    fn records_are_split_on_carriage_returns() {
+        let mut records = Vec::new();
+        for_each_record(&b"10%\r20%\r\n30%\n\n"[..], |r| records.push(r.to_string())).unwrap();
+        assert!(records == ["10%", "20%", "30%"]);
+    }
+
+    #[test]
+    fn sizes_and_durations_are_parsed() {
+        assert!(parse_size("1.5", "GiB") == Some(3 << 29));
+        assert!(parse_size("300MiB", "") == Some(300 << 20));
+        assert!(parse_size("12", "apples").is_none());
+        assert!(parse_duration("1:02:03") == Some(3723));
+        assert!(parse_duration("45s") == Some(45));
+        assert!(format_size(1536) == "1.5 KiB");
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{self, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use tempfile::NamedTempFile;
+
+use crate::compress::temp_dir;
+
+/// 求多个输入路径父目录的最深公共祖先，作为 mkdwarfs 的 `-i` 根目录。
+///
+/// 输入路径应为绝对路径。位于不同盘符时没有公共祖先，返回错误。
+pub fn common_root(inputs: &[PathBuf]) -> Result<PathBuf> {
+    let mut parents = inputs.iter().map(|input| {
+        input
+            .parent()
+            .with_context(|| format!("can't get parent path of {}", input.display()))
+    });
+    let mut root = parents
+        .next()
+        .context("No input path given")??
+        .to_path_buf();
+    for parent in parents {
+        let parent = parent?;
+        while !parent.starts_with(&root) {
+            ensure!(
+                root.pop(),
+                "Input paths have no common parent folder: {} and {}",
+                root.display(),
+                parent.display()
+            );
+        }
+    }
+    ensure!(
+        root.components().next().is_some(),
+        "Input paths have no common parent folder"
+    );
+    Ok(root)
+}
+
+/// 收集 `path` 及其下所有条目相对于 `root` 的路径，不跟随符号链接。
+fn collect_entries(root: &Path, path: &Path, entries: &mut BTreeSet<PathBuf>) -> Result<()> {
+    let relative = path.strip_prefix(root)?;
+    // 补上根目录与输入之间的中间目录，保证父目录先于子条目出现
+    for ancestor in relative.ancestors().skip(1) {
+        if !ancestor.as_os_str().is_empty() {
+            entries.insert(ancestor.to_path_buf());
+        }
+    }
+    entries.insert(relative.to_path_buf());
+    if fs::symlink_metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_entries(root, &entry?.path(), entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// 交给 mkdwarfs `--input-list` 的条目清单。
+///
+/// 只是把 `root` 下需要打包的相对路径写进临时文件，用户的数据不会被移动或复制。
+pub struct InputList {
+    pub root: PathBuf,
+    /// 用户给出的输入（绝对路径）
+    pub inputs: Vec<PathBuf>,
+    pub entries: BTreeSet<PathBuf>,
+}
+
+impl InputList {
+    /// 从若干个路径构造清单，根目录为它们的公共父目录。相对路径按当前目录解析。
+    pub fn from_inputs(inputs: &[PathBuf]) -> Result<Self> {
+        let inputs = inputs
+            .iter()
+            .map(path::absolute)
+            .collect::<io::Result<Vec<_>>>()?;
+        let root = common_root(&inputs)?;
+        let mut entries = BTreeSet::new();
+        for input in &inputs {
+            ensure!(
+                input.exists(),
+                "Input path does not exist: {}",
+                input.display()
+            );
+            collect_entries(&root, input, &mut entries)?;
+        }
+        Ok(Self {
+            root,
+            inputs,
+            entries,
+        })
+    }
+
+    /// 列出文件夹下的全部条目，根目录为该文件夹本身。
+    pub fn from_folder(folder: &Path) -> Result<Self> {
+        let root = path::absolute(folder)?;
+        let mut entries = BTreeSet::new();
+        for entry in fs::read_dir(&root)? {
+            collect_entries(&root, &entry?.path(), &mut entries)?;
+        }
+        Ok(Self {
+            inputs: vec![root.clone()],
+            root,
+            entries,
+        })
+    }
+
+    /// 写入临时文件，文件在返回值 drop 时删除。
+    pub fn write(&self) -> Result<NamedTempFile> {
+        let mut file = NamedTempFile::new_in(temp_dir())?;
+        {
+            let mut writer = BufWriter::new(&mut file);
+            for entry in &self.entries {
+                let entry = entry
+                    .to_str()
+                    .with_context(|| format!("Path is not valid UTF-8: {}", entry.display()))?;
+                writeln!(writer, "{entry}")?;
+            }
+            writer.flush()?;
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_root_of_siblings_is_their_parent() {
+        let root = common_root(&[PathBuf::from("/data/a"), PathBuf::from("/data/b.txt")]).unwrap();
+        assert!(root == Path::new("/data"));
+        let root = common_root(&[PathBuf::from("/data/x/a"), PathBuf::from("/data/y/b")]).unwrap();
+        assert!(root == Path::new("/data"));
+    }
+
+    #[test]
+    fn single_file_is_listed_from_its_parent_without_siblings() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), "d").unwrap();
+        fs::create_dir(dir.path().join("data")).unwrap();
+
+        let list = InputList::from_inputs(&[dir.path().join("data.bin")]).unwrap();
+        assert!(list.root == dir.path());
+        assert!(list.entries.iter().eq([Path::new("data.bin")]));
+    }
+
+    #[test]
+    fn input_list_contains_inputs_recursively_and_nothing_else() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/sub")).unwrap();
+        fs::write(dir.path().join("a/sub/f.txt"), "f").unwrap();
+        fs::write(dir.path().join("b.txt"), "b").unwrap();
+        fs::write(dir.path().join("ignored.txt"), "x").unwrap();
+
+        let list =
+            InputList::from_inputs(&[dir.path().join("a"), dir.path().join("b.txt")]).unwrap();
+        assert!(list.root == dir.path());
+        let expected: BTreeSet<PathBuf> = ["a", "a/sub", "a/sub/f.txt", "b.txt"]
+            .into_iter()
+            .map(|p| PathBuf::from(p).components().collect())
+            .collect();
+        assert!(list.entries == expected);
+    }
+}
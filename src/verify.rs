@@ -0,0 +1,318 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{self, File},
+    io,
+    path::{Component, Path},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compress::temp_dir,
+    list::{Entry, ListFilter, list_archive},
+    progress::status,
+    staging::InputList,
+};
+
+/// 校验失败时最多列出的差异条数。
+const MAX_REPORTED_MISMATCHES: usize = 20;
+
+/// 文件夹在对比表中的取值：文件夹只比较是否存在。
+const DIRECTORY: &str = "<directory>";
+
+/// 符号链接在对比表中的取值，统一为 `/` 分隔，去掉 Windows 的 `\\?\` 前缀。
+fn link_value(target: &str) -> String {
+    let target = target.replace('\\', "/");
+    format!("-> {}", target.strip_prefix("//?/").unwrap_or(&target))
+}
+
+/// 归档中文件夹和符号链接的对比取值，普通文件由 [`archive_checksums`] 提供。
+fn archive_structure(entries: &[Entry]) -> BTreeMap<String, String> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let value = match &entry.target {
+                Some(target) => link_value(target),
+                None if entry.is_dir() => DIRECTORY.to_string(),
+                None => return None,
+            };
+            Some((entry.path.clone(), value))
+        })
+        .collect()
+}
+
+/// 把相对路径转为归档内使用的 `/` 分隔形式。
+pub fn archive_path(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+/// 解析 `dwarfsck --checksum` 的输出，每行形如 `<hex>  <path>`。
+fn parse_checksums(output: &str) -> Result<BTreeMap<String, String>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (hash, path) = line
+                .split_once("  ")
+                .with_context(|| format!("Unexpected dwarfsck output: `{line}`"))?;
+            Ok((
+                path.trim_start_matches(['/', '*']).replace('\\', "/"),
+                hash.trim().to_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+/// 对比归档内文件的哈希与源文件，返回差异描述。
+///
+/// `complete` 为 `false`（使用了过滤规则）时，源中有而归档中没有的文件不算差异。
+fn compare(
+    source: &BTreeMap<String, String>,
+    archive: &BTreeMap<String, String>,
+    complete: bool,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for (path, hash) in source {
+        match archive.get(path) {
+            Some(archived) if archived != hash => {
+                mismatches.push(format!("content differs: {path}"));
+            },
+            None if complete => mismatches.push(format!("missing from archive: {path}")),
+            _ => {},
+        }
+    }
+    for path in archive.keys() {
+        if !source.contains_key(path) {
+            mismatches.push(format!("not in source: {path}"));
+        }
+    }
+    mismatches
+}
+
+/// 计算源文件的 SHA-256，键为归档内的路径。文件夹和符号链接分别以 [`DIRECTORY`] 和
+/// [`link_value`] 表示。
+///
+/// 只读取归档中也有的条目。其余条目在 `complete` 为 `true` 时以空值列出，只用于报告缺失；
+/// 使用了过滤规则时它们本来就被排除在外，直接跳过。
+fn source_hashes(
+    source: &InputList,
+    archive: &BTreeMap<String, String>,
+    complete: bool,
+) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    for entry in &source.entries {
+        let path = source.root.join(entry);
+        let meta = fs::symlink_metadata(&path)?;
+        // 设备等特殊文件不会被打包
+        if !(meta.is_file() || meta.is_dir() || meta.is_symlink()) {
+            continue;
+        }
+        let key = archive_path(entry);
+        if !archive.contains_key(&key) {
+            if complete {
+                hashes.insert(key, String::new());
+            }
+            continue;
+        }
+        let value = if meta.is_symlink() {
+            link_value(&fs::read_link(&path)?.to_string_lossy())
+        } else if meta.is_dir() {
+            DIRECTORY.to_string()
+        } else {
+            sha256_file(&path)?
+        };
+        hashes.insert(key, value);
+    }
+    Ok(hashes)
+}
+
+/// 用 dwarfsck 检查镜像各部分的校验和。
+pub fn check_integrity(image: &Path) -> Result<()> {
+    let status = Command::new(temp_dir().join("dwarfsck.exe"))
+        .arg("--check-integrity")
+        .arg("-q")
+        .arg(image)
+        .status()?;
+    ensure!(
+        status.success(),
+        "Integrity check failed: dwarfsck exited with {status}"
+    );
+    Ok(())
+}
+
+/// 用 dwarfsck 计算归档内每个文件的 SHA-256，键为归档内的路径。
+pub fn archive_checksums(image: &Path) -> Result<BTreeMap<String, String>> {
+    let output = Command::new(temp_dir().join("dwarfsck.exe"))
+        .arg("--checksum=sha256")
+        .arg(image)
+        .stderr(Stdio::inherit())
+        .output()?;
+    ensure!(
+        output.status.success(),
+        "dwarfsck exited with {}",
+        output.status
+    );
+    parse_checksums(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// 校验新生成的镜像：先用 dwarfsck 做完整性检查，再逐个对比条目列表、文件的 SHA-256 和
+/// 符号链接的目标。
+///
+/// 文件大小不同必然导致哈希不同，因此大小差异也会以内容差异报告。
+pub fn verify_image(image: &Path, source: &InputList, complete: bool) -> Result<()> {
+    status(format_args!("Verifying {}", image.display()));
+    check_integrity(image)?;
+    let mut archive = archive_checksums(image)?;
+    archive.extend(archive_structure(&list_archive(
+        image,
+        &ListFilter::default(),
+    )?));
+    let hashes = source_hashes(source, &archive, complete)?;
+    let mismatches = compare(&hashes, &archive, complete);
+    if mismatches.is_empty() {
+        status(format_args!("Verified {} entries", archive.len()));
+        return Ok(());
+    }
+    for mismatch in mismatches.iter().take(MAX_REPORTED_MISMATCHES) {
+        eprintln!("  {mismatch}");
+    }
+    if mismatches.len() > MAX_REPORTED_MISMATCHES {
+        eprintln!(
+            "  ... and {} more",
+            mismatches.len() - MAX_REPORTED_MISMATCHES
+        );
+    }
+    bail!(
+        "Verification failed: {} mismatches between {} and its source",
+        mismatches.len(),
+        image.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn checksums_are_parsed_with_normalized_paths() {
+        let parsed = parse_checksums("ABCD  dir/a.txt\nef01  /b.txt\n\n").unwrap();
+        assert!(parsed == map(&[("dir/a.txt", "abcd"), ("b.txt", "ef01")]));
+        assert!(parse_checksums("garbage").is_err());
+    }
+
+    #[test]
+    fn compare_reports_every_kind_of_difference() {
+        let source = map(&[("same", "1"), ("changed", "2"), ("missing", "3")]);
+        let archive = map(&[("same", "1"), ("changed", "x"), ("extra", "4")]);
+        let mismatches = compare(&source, &archive, true);
+        assert!(
+            mismatches
+                == [
+                    "content differs: changed",
+                    "missing from archive: missing",
+                    "not in source: extra"
+                ]
+        );
+        // 使用过滤规则时，源中被排除的文件不算差异
+        assert!(compare(&source, &archive, false).len() == 2);
+    }
+
+    #[test]
+    fn only_archived_source_files_are_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kept.txt"), "abc").unwrap();
+        fs::write(dir.path().join("excluded.tmp"), "abc").unwrap();
+        let source = InputList::from_folder(dir.path()).unwrap();
+        let archive = map(&[("kept.txt", "ba78")]);
+
+        let hashes = source_hashes(&source, &archive, false).unwrap();
+        assert!(hashes.keys().collect::<Vec<_>>() == ["kept.txt"]);
+        assert!(hashes["kept.txt"].starts_with("ba7816bf"));
+        // 不使用过滤规则时，归档中没有的文件也要列出以便报告缺失，但不读取内容
+        let hashes = source_hashes(&source, &archive, true).unwrap();
+        assert!(hashes["excluded.tmp"].is_empty());
+    }
+
+    #[test]
+    fn folders_and_links_are_compared_by_kind_and_target() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        #[cfg(windows)]
+        let created = std::os::windows::fs::symlink_file("sub\\a.txt", dir.path().join("link"));
+        #[cfg(not(windows))]
+        let created = std::os::unix::fs::symlink("sub/a.txt", dir.path().join("link"));
+        // 创建符号链接需要开发者模式或管理员权限
+        if created.is_err() {
+            return;
+        }
+        let entry = |path: &str, mode: &str, target: Option<&str>| Entry {
+            path: path.to_string(),
+            mode: mode.to_string(),
+            size: 0,
+            mtime: 0,
+            mtime_resolution: 1,
+            target: target.map(str::to_string),
+        };
+        let archive = archive_structure(&[
+            entry("sub", "drwxr-xr-x", None),
+            entry("link", "lrwxrwxrwx", Some("sub/a.txt")),
+            entry("file", "-rw-r--r--", None),
+        ]);
+        assert!(archive == map(&[("sub", DIRECTORY), ("link", "-> sub/a.txt")]));
+
+        let source = InputList::from_folder(dir.path()).unwrap();
+        assert!(
+            compare(
+                &source_hashes(&source, &archive, true).unwrap(),
+                &archive,
+                true
+            )
+            .is_empty()
+        );
+        let moved = map(&[("sub", DIRECTORY), ("link", "-> elsewhere")]);
+        let hashes = source_hashes(&source, &moved, true).unwrap();
+        assert!(compare(&hashes, &moved, true) == ["content differs: link"]);
+    }
+
+    #[test]
+    fn sha256_matches_known_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("abc.txt");
+        fs::write(&file, "abc").unwrap();
+        assert!(
+            sha256_file(&file).unwrap()
+                == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(archive_path(Path::new("a/b/c.txt")) == "a/b/c.txt");
+    }
+}